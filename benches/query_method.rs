@@ -0,0 +1,62 @@
+use actix_web::{rt::System, test, web, App};
+use actix_web_query_method_middleware::QueryMethod;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+fn bench_call(c: &mut Criterion) {
+    let system = System::new();
+    let without_middleware = system.block_on(test::init_service(
+        App::new().default_service(web::to(|| async { "OK" })),
+    ));
+    let with_middleware = system.block_on(test::init_service(
+        App::new()
+            .wrap(QueryMethod::new())
+            .default_service(web::to(|| async { "OK" })),
+    ));
+
+    // Creating the requests is not measured, only handling them.
+    let mut group = c.benchmark_group("call");
+    group.bench_function("without middleware", |b| {
+        b.iter_batched(
+            || {
+                test::TestRequest::post()
+                    .uri("/items?id=1&name=item")
+                    .to_request()
+            },
+            |req| system.block_on(test::call_service(&without_middleware, req)),
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("parameter absent", |b| {
+        b.iter_batched(
+            || {
+                test::TestRequest::post()
+                    .uri("/items?id=1&name=item")
+                    .to_request()
+            },
+            |req| system.block_on(test::call_service(&with_middleware, req)),
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("parameter absent, no query", |b| {
+        b.iter_batched(
+            || test::TestRequest::post().uri("/items").to_request(),
+            |req| system.block_on(test::call_service(&with_middleware, req)),
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("parameter present", |b| {
+        b.iter_batched(
+            || {
+                test::TestRequest::post()
+                    .uri("/items?id=1&_method=PUT")
+                    .to_request()
+            },
+            |req| system.block_on(test::call_service(&with_middleware, req)),
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_call);
+criterion_main!(benches);
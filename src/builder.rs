@@ -0,0 +1,639 @@
+//! A builder for [`QueryMethod`], which checks the configuration before
+//! creating the middleware.
+use std::fmt;
+use std::sync::Arc;
+
+use actix_web::dev::ServiceRequest;
+use actix_web::http::{Method, StatusCode};
+
+use crate::events::SharedEventHandler;
+use crate::filter::SharedFilter;
+use crate::origin::{normalize_origin, SameOrigin};
+use crate::path_pattern::PathPattern;
+#[cfg(feature = "metrics")]
+use crate::QueryMethodMetrics;
+use crate::{CsrfToken, IntoOverrideDecision, QueryMethod, QueryMethodEvent, StrictModePolicy};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+/// A problem with the configuration given to [`QueryMethodBuilder`].
+pub enum ConfigError {
+    /// The method parameter name is empty.
+    EmptyParameterName,
+    /// The method parameter name contains characters that can't be used in a
+    /// query parameter name, like `=` or `&`.
+    InvalidParameterName(String),
+    /// A path pattern is not valid. Patterns must start with a `/`, and `**`
+    /// must be a whole path segment.
+    InvalidPathPattern(String),
+    /// The CSRF token name is empty or contains characters that can't be used
+    /// in a query parameter name.
+    InvalidCsrfTokenName(String),
+    /// The CSRF cookie name is empty or contains characters that can't be
+    /// used in a cookie name.
+    InvalidCsrfCookieName(String),
+    /// The CSRF header name is not a valid header name.
+    InvalidCsrfHeaderName(String),
+    /// A [`StrictModePolicy::Reject`] status code is not an error code.
+    InvalidRejectStatus(StatusCode),
+    /// A method alias is empty.
+    EmptyMethodAlias,
+    /// An allowed origin is not an origin like `https://example.com`. Origins
+    /// have a scheme and a host, an optional port, and no path.
+    InvalidOrigin(String),
+    /// A list of methods that may be rerouted is empty.
+    EmptyMethodList,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyParameterName => write!(f, "method parameter name is empty"),
+            Self::InvalidParameterName(name) => {
+                write!(f, "method parameter name {:?} is not valid", name)
+            }
+            Self::InvalidPathPattern(pattern) => {
+                write!(f, "path pattern {:?} is not valid", pattern)
+            }
+            Self::InvalidCsrfTokenName(name) => {
+                write!(f, "CSRF token name {:?} is not valid", name)
+            }
+            Self::InvalidCsrfCookieName(name) => {
+                write!(f, "CSRF cookie name {:?} is not valid", name)
+            }
+            Self::InvalidCsrfHeaderName(name) => {
+                write!(f, "CSRF header name {:?} is not valid", name)
+            }
+            Self::InvalidRejectStatus(status) => {
+                write!(f, "status {} is not an error status to reject with", status)
+            }
+            Self::EmptyMethodAlias => write!(f, "method alias is empty"),
+            Self::InvalidOrigin(origin) => write!(f, "origin {:?} is not valid", origin),
+            Self::EmptyMethodList => write!(f, "list of methods that may be rerouted is empty"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Whether the name can be used as a query parameter name as-is.
+pub(crate) fn is_valid_parameter_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.chars().any(|c| {
+            matches!(c, '=' | '&' | '#' | '?' | '+' | '%') || c.is_whitespace() || c.is_control()
+        })
+}
+
+#[derive(Clone, Debug, Default)]
+/// Creates a [`QueryMethod`] middleware.
+///
+/// The builder checks the configuration when you call
+/// [`QueryMethodBuilder::build`], so mistakes like an empty parameter name are
+/// caught when your server starts instead of silently breaking requests.
+///
+/// ```rs
+/// let query_method = QueryMethod::builder()
+///     .parameter_name("_my_method")
+///     .strict_mode(true)
+///     .include_paths(["/admin/**"])
+///     .build()
+///     .expect("query method configuration is valid");
+/// ```
+pub struct QueryMethodBuilder {
+    /// The settings that don't need any checks or conversions, and the
+    /// defaults for all settings.
+    options: QueryMethod,
+    include_paths: Vec<String>,
+    exclude_paths: Vec<String>,
+    csrf_token: Option<CsrfToken>,
+    enforce_same_origin: bool,
+    allowed_origins: Vec<String>,
+    allow_missing_origin: bool,
+    allowed_original_methods_for_paths: Vec<(Vec<String>, Vec<Method>)>,
+}
+
+impl QueryMethodBuilder {
+    /// Start building the middleware with the default settings.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The parameter name to use. By default this is `_method`, meaning that
+    /// you need to send your request like `/path?_method=PUT` to use this
+    /// middleware. If you happen to already use `_method` in your application,
+    /// you can override the parameter name used here to pick something else.
+    ///
+    /// The name can't be empty, and can't contain characters that would need
+    /// to be escaped in a query string like `=` or `&`.
+    #[must_use]
+    pub fn parameter_name<N: Into<String>>(mut self, name: N) -> Self {
+        self.options.parameter_name = name.into();
+        self
+    }
+
+    /// Disabled by default. When enabled, the middleware will respond to
+    /// requests that have the method parameter but were sent with a method
+    /// that can't be rerouted, like `GET`, by rejecting them with a 400 code
+    /// response. When disabled, these requests are passed on to
+    /// your server unchanged.
+    ///
+    /// This is a shortcut for [`QueryMethodBuilder::strict_mode_policy`] with
+    /// `StrictModePolicy::Reject { status: StatusCode::BAD_REQUEST }` or
+    /// `StrictModePolicy::PassThrough`.
+    #[must_use]
+    pub fn strict_mode(mut self, enabled: bool) -> Self {
+        self.options.strict_mode = if enabled {
+            StrictModePolicy::STRICT
+        } else {
+            StrictModePolicy::PassThrough
+        };
+        self
+    }
+
+    /// What to do with requests that have the method parameter but were sent
+    /// with a method that can't be rerouted. By default this is
+    /// [`StrictModePolicy::PassThrough`].
+    #[must_use]
+    pub fn strict_mode_policy(mut self, policy: StrictModePolicy) -> Self {
+        self.options.strict_mode = policy;
+        self
+    }
+
+    /// What to do with requests sent with this method that have the method
+    /// parameter, overriding [`QueryMethodBuilder::strict_mode_policy`] for
+    /// this method.
+    #[must_use]
+    pub fn strict_mode_policy_for(mut self, method: Method, policy: StrictModePolicy) -> Self {
+        self.options.strict_mode_methods.insert(method, policy);
+        self
+    }
+
+    /// Only reroute requests that were sent with one of these methods. By
+    /// default only `POST` requests are rerouted.
+    ///
+    /// Be careful with adding methods like `GET`: a link or an image on
+    /// another site can make the browser send a `GET` request, so a
+    /// `GET /item?_method=DELETE` would let anyone delete your items. If you
+    /// need this, keep it to as few paths as you can with
+    /// [`QueryMethodBuilder::allowed_original_methods_for_paths`], and think
+    /// about what else can protect these requests, like
+    /// [`QueryMethodBuilder::filter`] or
+    /// [`QueryMethodBuilder::enforce_same_origin`].
+    ///
+    /// The list can't be empty. To turn the middleware off for some paths,
+    /// use [`QueryMethodBuilder::exclude_paths`] instead.
+    #[must_use]
+    pub fn allowed_original_methods<I>(mut self, methods: I) -> Self
+    where
+        I: IntoIterator<Item = Method>,
+    {
+        self.options.allowed_original_methods = methods.into_iter().collect();
+        self
+    }
+
+    /// Only reroute requests to paths matching one of these patterns that
+    /// were sent with one of these methods, overriding
+    /// [`QueryMethodBuilder::allowed_original_methods`] for these paths. See
+    /// [`QueryMethodBuilder::include_paths`] for the pattern syntax. If a
+    /// path matches the patterns of more than one call, the first call wins.
+    ///
+    /// ```rs
+    /// QueryMethod::builder()
+    ///     // A legacy client can only send GET requests to purge the cache
+    ///     .allowed_original_methods_for_paths(
+    ///         ["/admin/cache"],
+    ///         [Method::POST, Method::GET],
+    ///     )
+    ///     .build()
+    /// ```
+    #[must_use]
+    pub fn allowed_original_methods_for_paths<I, P, M>(mut self, patterns: I, methods: M) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<String>,
+        M: IntoIterator<Item = Method>,
+    {
+        self.allowed_original_methods_for_paths.push((
+            patterns.into_iter().map(Into::into).collect(),
+            methods.into_iter().collect(),
+        ));
+        self
+    }
+
+    /// Disabled by default. When enabled, the middleware removes the method
+    /// parameter from every request that has it, even from requests that it
+    /// doesn't reroute, like a `GET /x?_method=PUT` that is passed on to your
+    /// server. This keeps the parameter away from your handlers and query
+    /// extractors, and gives everything that sees the request afterwards,
+    /// like caches keyed on the URI, the same normalized URI whether the
+    /// parameter was sent or not.
+    ///
+    /// Requests to paths the middleware doesn't apply to, and requests it
+    /// rejects, are not changed.
+    #[must_use]
+    pub fn always_strip_parameter(mut self) -> Self {
+        self.options.always_strip_parameter = true;
+        self
+    }
+
+    /// Enabled by default. When enabled, the method parameter is
+    /// case-insensitive, so `?_method=delete` is rerouted to `DELETE`. When
+    /// disabled, the value is used as the method exactly as it was sent, which
+    /// you may need if you use custom methods that aren't all uppercase.
+    #[must_use]
+    pub fn normalize_method_case(mut self, enabled: bool) -> Self {
+        self.options.normalize_method_case = enabled;
+        self
+    }
+
+    /// Reroute requests where the method parameter is `alias` to `method`.
+    /// Aliases are case-insensitive. For example with
+    /// `.method_alias("remove", Method::DELETE)`, a request to
+    /// `/item?_method=remove` is rerouted to `DELETE`.
+    #[must_use]
+    pub fn method_alias<A: Into<String>>(mut self, alias: A, method: Method) -> Self {
+        self.options
+            .method_aliases
+            .insert(alias.into().to_ascii_lowercase(), method);
+        self
+    }
+
+    /// Only apply the middleware to requests with paths matching one of these
+    /// patterns. By default the middleware applies to all paths.
+    ///
+    /// A pattern without a `*` is a prefix, so `/admin` matches `/admin` and
+    /// `/admin/users` but not `/administrator`. A pattern with a `*` is a glob,
+    /// where `*` matches anything within one path segment and `**` matches any
+    /// number of segments, like `/admin/**` or `/items/*/edit`. Paths are
+    /// matched after they are percent-decoded, the same way Actix Web matches
+    /// routes, so `/ad%6Din` matches `/admin`.
+    ///
+    /// Requests to other paths pass through untouched, even in strict mode.
+    #[must_use]
+    pub fn include_paths<I, P>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<String>,
+    {
+        self.include_paths
+            .extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    /// Never apply the middleware to requests with paths matching one of these
+    /// patterns, even if they match [`QueryMethodBuilder::include_paths`]. See
+    /// `include_paths` for the pattern syntax.
+    ///
+    /// Requests to these paths pass through untouched, even in strict mode.
+    #[must_use]
+    pub fn exclude_paths<I, P>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<String>,
+    {
+        self.exclude_paths
+            .extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    /// Disabled by default. When enabled, the middleware will only reroute
+    /// requests that carry a valid CSRF token, and reject the others with a
+    /// 403 code response. `POST` requests without the method parameter are
+    /// not checked.
+    ///
+    /// You can pass the name of the token, in which case the token is
+    /// compared against a cookie with the same name, or a [`CsrfToken`] to
+    /// configure how the token is found and checked.
+    #[must_use]
+    pub fn require_csrf_token<T: Into<CsrfToken>>(mut self, token: T) -> Self {
+        self.csrf_token = Some(token.into());
+        self
+    }
+
+    /// Decide whether each request may be rerouted. The filter gets the
+    /// request and the method it would be rerouted to, and runs before the
+    /// request is changed. It can return an [`OverrideDecision`] directly, or
+    /// a future that resolves to one if it needs to do async work. The future
+    /// can't borrow the request, so copy out anything you need from it first.
+    ///
+    /// If you also require a CSRF token, the token is only checked for
+    /// requests the filter allows.
+    ///
+    /// [`OverrideDecision`]: crate::OverrideDecision
+    #[must_use]
+    pub fn filter<F, D>(mut self, filter: F) -> Self
+    where
+        F: Fn(&ServiceRequest, &Method) -> D + Send + Sync + 'static,
+        D: IntoOverrideDecision,
+    {
+        self.options.filter = Some(SharedFilter(Arc::new(
+            move |req: &ServiceRequest, method: &Method| filter(req, method).into_decision(),
+        )));
+        self
+    }
+
+    /// Disabled by default. When enabled, the middleware will only reroute
+    /// requests that came from an allowed origin, and reject the others with
+    /// a 403 code response. The origin is taken from the `Origin` header, or
+    /// from the `Referer` header if there is no `Origin` header.
+    ///
+    /// By default the only allowed origin is the one of your server, as seen
+    /// by the request: the `Host` header, with `https` if the server is
+    /// listening with TLS and `http` otherwise. `Forwarded` and
+    /// `X-Forwarded-*` headers are ignored since clients can send them
+    /// themselves, so if your server is behind a proxy that terminates TLS or
+    /// changes the host, pick the allowed origins yourself with
+    /// [`QueryMethodBuilder::allowed_origins`].
+    #[must_use]
+    pub fn enforce_same_origin(mut self) -> Self {
+        self.enforce_same_origin = true;
+        self
+    }
+
+    /// Only reroute requests from these origins, like `https://example.com`.
+    /// This also enables [`QueryMethodBuilder::enforce_same_origin`].
+    #[must_use]
+    pub fn allowed_origins<I, O>(mut self, origins: I) -> Self
+    where
+        I: IntoIterator<Item = O>,
+        O: Into<String>,
+    {
+        self.enforce_same_origin = true;
+        self.allowed_origins
+            .extend(origins.into_iter().map(Into::into));
+        self
+    }
+
+    /// Disabled by default. When enabled, requests that have neither an
+    /// `Origin` nor a `Referer` header are rerouted even when same origin is
+    /// enforced. Browsers always send one of these for cross-origin form
+    /// submissions, so you may want to enable this for non-browser clients.
+    ///
+    /// This also enables [`QueryMethodBuilder::enforce_same_origin`].
+    #[must_use]
+    pub fn allow_missing_origin(mut self, allowed: bool) -> Self {
+        self.enforce_same_origin = true;
+        self.allow_missing_origin = allowed;
+        self
+    }
+
+    /// Call this function whenever the middleware reroutes or rejects a
+    /// request. Rejections are reported before responding, and reroutes
+    /// once your server has handled the request, so that reroutes undone by
+    /// [`SkipQueryMethod`](crate::SkipQueryMethod) aren't reported. The
+    /// function is called while the request is being handled, so it should
+    /// be quick.
+    #[must_use]
+    pub fn on_event<F>(mut self, on_event: F) -> Self
+    where
+        F: Fn(QueryMethodEvent) + Send + Sync + 'static,
+    {
+        self.options.on_event = Some(SharedEventHandler(Arc::new(on_event)));
+        self
+    }
+
+    /// Count the requests the middleware reroutes or rejects with these
+    /// metrics. You can keep a clone of the metrics to read the counts.
+    #[cfg(feature = "metrics")]
+    #[must_use]
+    pub fn metrics(mut self, metrics: QueryMethodMetrics) -> Self {
+        self.options.metrics = Some(metrics);
+        self
+    }
+
+    /// Check the configuration, and create the middleware.
+    pub fn build(self) -> Result<QueryMethod, ConfigError> {
+        if self.options.parameter_name.is_empty() {
+            return Err(ConfigError::EmptyParameterName);
+        }
+        if !is_valid_parameter_name(&self.options.parameter_name) {
+            return Err(ConfigError::InvalidParameterName(
+                self.options.parameter_name,
+            ));
+        }
+        let csrf_token = self.csrf_token.map(CsrfToken::build).transpose()?;
+        if self.options.method_aliases.contains_key("") {
+            return Err(ConfigError::EmptyMethodAlias);
+        }
+        for policy in std::iter::once(&self.options.strict_mode)
+            .chain(self.options.strict_mode_methods.values())
+        {
+            if let StrictModePolicy::Reject { status } = policy {
+                if !policy.is_valid() {
+                    return Err(ConfigError::InvalidRejectStatus(*status));
+                }
+            }
+        }
+
+        if self.options.allowed_original_methods.is_empty()
+            || self
+                .allowed_original_methods_for_paths
+                .iter()
+                .any(|(_, methods)| methods.is_empty())
+        {
+            return Err(ConfigError::EmptyMethodList);
+        }
+        let allowed_original_methods_for_paths = self
+            .allowed_original_methods_for_paths
+            .into_iter()
+            .map(|(patterns, methods)| Ok((parse_path_patterns(patterns)?, methods)))
+            .collect::<Result<_, _>>()?;
+
+        let same_origin = if self.enforce_same_origin {
+            let allowed_origins = self
+                .allowed_origins
+                .into_iter()
+                .map(|origin| normalize_origin(&origin).ok_or(ConfigError::InvalidOrigin(origin)))
+                .collect::<Result<_, _>>()?;
+            Some(SameOrigin {
+                allowed_origins,
+                allow_missing: self.allow_missing_origin,
+            })
+        } else {
+            None
+        };
+
+        Ok(QueryMethod {
+            include_paths: parse_path_patterns(self.include_paths)?,
+            exclude_paths: parse_path_patterns(self.exclude_paths)?,
+            csrf_token: csrf_token.map(Arc::new),
+            same_origin,
+            allowed_original_methods_for_paths,
+            ..self.options
+        })
+    }
+}
+
+fn parse_path_patterns(patterns: Vec<String>) -> Result<Vec<PathPattern>, ConfigError> {
+    patterns
+        .into_iter()
+        .map(|pattern| PathPattern::parse(&pattern).ok_or(ConfigError::InvalidPathPattern(pattern)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_configuration_is_valid() {
+        assert!(QueryMethodBuilder::new().build().is_ok());
+    }
+
+    #[test]
+    fn test_invalid_parameter_names_are_rejected() {
+        assert_eq!(
+            QueryMethodBuilder::new().parameter_name("").build().err(),
+            Some(ConfigError::EmptyParameterName)
+        );
+        for name in ["_method=", "a&b", "with space", "50%"] {
+            assert_eq!(
+                QueryMethodBuilder::new().parameter_name(name).build().err(),
+                Some(ConfigError::InvalidParameterName(name.to_string())),
+                "{:?} is rejected",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_invalid_path_patterns_are_rejected() {
+        for pattern in ["", "admin", "/admin/a**", "/admin?x=y"] {
+            assert_eq!(
+                QueryMethodBuilder::new()
+                    .include_paths([pattern])
+                    .build()
+                    .err(),
+                Some(ConfigError::InvalidPathPattern(pattern.to_string())),
+                "{:?} is rejected",
+                pattern
+            );
+        }
+        assert_eq!(
+            QueryMethodBuilder::new()
+                .exclude_paths(["api/**"])
+                .build()
+                .err(),
+            Some(ConfigError::InvalidPathPattern("api/**".to_string())),
+        );
+    }
+
+    #[test]
+    fn test_invalid_csrf_token_is_rejected() {
+        assert_eq!(
+            QueryMethodBuilder::new()
+                .require_csrf_token("")
+                .build()
+                .err(),
+            Some(ConfigError::InvalidCsrfTokenName("".to_string()))
+        );
+        assert_eq!(
+            QueryMethodBuilder::new()
+                .require_csrf_token(CsrfToken::new("csrf").cookie_name("my cookie"))
+                .build()
+                .err(),
+            Some(ConfigError::InvalidCsrfCookieName("my cookie".to_string()))
+        );
+        assert_eq!(
+            QueryMethodBuilder::new()
+                .require_csrf_token(CsrfToken::new("csrf").header_name("X-CSRF:Token"))
+                .build()
+                .err(),
+            Some(ConfigError::InvalidCsrfHeaderName(
+                "X-CSRF:Token".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_non_error_reject_status_is_rejected() {
+        assert_eq!(
+            QueryMethodBuilder::new()
+                .strict_mode_policy(StrictModePolicy::Reject {
+                    status: StatusCode::OK
+                })
+                .build()
+                .err(),
+            Some(ConfigError::InvalidRejectStatus(StatusCode::OK))
+        );
+        assert_eq!(
+            QueryMethodBuilder::new()
+                .strict_mode_policy_for(
+                    Method::GET,
+                    StrictModePolicy::Reject {
+                        status: StatusCode::MOVED_PERMANENTLY
+                    }
+                )
+                .build()
+                .err(),
+            Some(ConfigError::InvalidRejectStatus(
+                StatusCode::MOVED_PERMANENTLY
+            ))
+        );
+    }
+
+    #[test]
+    fn test_empty_method_alias_is_rejected() {
+        assert_eq!(
+            QueryMethodBuilder::new()
+                .method_alias("", Method::DELETE)
+                .build()
+                .err(),
+            Some(ConfigError::EmptyMethodAlias)
+        );
+    }
+
+    #[test]
+    fn test_invalid_allowed_origins_are_rejected() {
+        assert_eq!(
+            QueryMethodBuilder::new()
+                .allowed_origins(["https://example.com/app"])
+                .build()
+                .err(),
+            Some(ConfigError::InvalidOrigin(
+                "https://example.com/app".to_string()
+            ))
+        );
+        assert!(QueryMethodBuilder::new()
+            .allowed_origins(["https://example.com", "http://localhost:8080"])
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_allow_missing_origin_enforces_same_origin() {
+        let options = QueryMethodBuilder::new()
+            .allow_missing_origin(true)
+            .build()
+            .unwrap();
+        assert!(options
+            .same_origin
+            .is_some_and(|same_origin| same_origin.allow_missing));
+    }
+
+    #[test]
+    fn test_empty_method_lists_are_rejected() {
+        assert_eq!(
+            QueryMethodBuilder::new()
+                .allowed_original_methods([])
+                .build()
+                .err(),
+            Some(ConfigError::EmptyMethodList)
+        );
+        assert_eq!(
+            QueryMethodBuilder::new()
+                .allowed_original_methods_for_paths(["/admin"], [])
+                .build()
+                .err(),
+            Some(ConfigError::EmptyMethodList)
+        );
+        assert_eq!(
+            QueryMethodBuilder::new()
+                .allowed_original_methods_for_paths(["admin"], [Method::GET])
+                .build()
+                .err(),
+            Some(ConfigError::InvalidPathPattern("admin".to_string()))
+        );
+    }
+}
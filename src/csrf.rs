@@ -0,0 +1,256 @@
+//! CSRF token verification for rerouted requests, configured with
+//! [`QueryMethodBuilder::require_csrf_token`](crate::QueryMethodBuilder::require_csrf_token).
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use actix_web::dev::{Payload, ServiceRequest};
+use actix_web::http::header::HeaderName;
+use actix_web::web::Bytes;
+use actix_web::{Error, HttpMessage};
+use futures::stream::{self, StreamExt};
+use qstring::QString;
+
+use crate::builder::is_valid_parameter_name;
+use crate::{ConfigError, RejectionReason};
+
+/// A function that decides if a CSRF token is valid for a request.
+pub type CsrfValidator = dyn Fn(&ServiceRequest, &str) -> bool + Send + Sync;
+
+#[derive(Clone)]
+/// The CSRF token a request must carry before the middleware will reroute it.
+///
+/// The token is looked up in the query string first, then in a header, and
+/// finally in the body of `application/x-www-form-urlencoded` requests, which
+/// is what HTML forms send. By default the token is verified with the
+/// double-submit pattern: it must be identical to the value of a cookie. You
+/// can replace this with your own check using [`CsrfToken::validator`].
+///
+/// You can create one from just the token name, in which case the cookie has
+/// the same name and the header is `X-CSRF-Token`.
+///
+/// ```rs
+/// QueryMethod::builder().require_csrf_token("csrf_token")
+/// // or
+/// QueryMethod::builder().require_csrf_token(
+///     CsrfToken::new("csrf_token")
+///         .cookie_name("__Host-csrf")
+///         .header_name("X-XSRF-Token"),
+/// )
+/// ```
+pub struct CsrfToken {
+    name: String,
+    cookie_name: String,
+    header_name: String,
+    /// The parsed `header_name`, set once the configuration is checked.
+    header: Option<HeaderName>,
+    body_limit: usize,
+    validator: Option<Arc<CsrfValidator>>,
+}
+
+impl CsrfToken {
+    /// Look for a token with this name in the query string and the form body.
+    #[must_use]
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            cookie_name: name.to_string(),
+            header_name: "X-CSRF-Token".to_string(),
+            header: None,
+            body_limit: 64 * 1024,
+            validator: None,
+        }
+    }
+
+    /// The cookie the token is compared against. By default this is the same
+    /// as the token name.
+    #[must_use]
+    pub fn cookie_name(mut self, name: &str) -> Self {
+        self.cookie_name = name.to_string();
+        self
+    }
+
+    /// The header to look for the token in. By default this is `X-CSRF-Token`.
+    #[must_use]
+    pub fn header_name(mut self, name: &str) -> Self {
+        self.header_name = name.to_string();
+        self
+    }
+
+    /// The largest form body, in bytes, that will be searched for the token.
+    /// Larger bodies are not searched, so unless the token is also in the
+    /// query string or the header, the request is rejected as if it had no
+    /// token. By default this is 64 KiB.
+    #[must_use]
+    pub fn body_limit(mut self, limit: usize) -> Self {
+        self.body_limit = limit;
+        self
+    }
+
+    /// Check the token with this function instead of comparing it to a
+    /// cookie. The function gets the request and the token the request
+    /// carried, and should return `true` if the token is valid.
+    #[must_use]
+    pub fn validator<F>(mut self, validator: F) -> Self
+    where
+        F: Fn(&ServiceRequest, &str) -> bool + Send + Sync + 'static,
+    {
+        self.validator = Some(Arc::new(validator));
+        self
+    }
+
+    /// Check that the names in this configuration can actually be used, and
+    /// parse the header name so it doesn't have to be parsed for every request.
+    pub(crate) fn build(mut self) -> Result<Self, ConfigError> {
+        if !is_valid_parameter_name(&self.name) {
+            return Err(ConfigError::InvalidCsrfTokenName(self.name.clone()));
+        }
+        if !is_valid_parameter_name(&self.cookie_name) || self.cookie_name.contains([',', ';']) {
+            return Err(ConfigError::InvalidCsrfCookieName(self.cookie_name.clone()));
+        }
+        match HeaderName::from_str(&self.header_name) {
+            Ok(header) => self.header = Some(header),
+            Err(_) => return Err(ConfigError::InvalidCsrfHeaderName(self.header_name)),
+        }
+        Ok(self)
+    }
+
+    /// Find the token in the request, and check if it is valid.
+    ///
+    /// If the token has to be read from the body, the body is put back into
+    /// the request afterwards so your server can still read it.
+    pub(crate) async fn check(&self, req: &mut ServiceRequest) -> Result<CsrfCheck, Error> {
+        let token = match self.find_token_in_head(req) {
+            Some(token) => Some(token),
+            None => self.find_token_in_body(req).await?,
+        };
+        let Some(token) = token else {
+            return Ok(CsrfCheck::Missing);
+        };
+
+        let valid = match &self.validator {
+            Some(validator) => validator(req, &token),
+            None => req
+                .cookie(&self.cookie_name)
+                .is_some_and(|cookie| constant_time_eq(cookie.value(), &token)),
+        };
+        Ok(if valid {
+            CsrfCheck::Valid
+        } else {
+            CsrfCheck::Invalid
+        })
+    }
+
+    fn find_token_in_head(&self, req: &ServiceRequest) -> Option<String> {
+        if let Some(token) = QString::from(req.query_string()).get(&self.name) {
+            return Some(token.to_string());
+        }
+        self.header
+            .as_ref()
+            .and_then(|name| req.headers().get(name))
+            .and_then(|value| value.to_str().ok())
+            .map(ToString::to_string)
+    }
+
+    async fn find_token_in_body(&self, req: &mut ServiceRequest) -> Result<Option<String>, Error> {
+        if !req.content_type().eq_ignore_ascii_case(FORM_CONTENT_TYPE) {
+            return Ok(None);
+        }
+
+        let mut payload = req.take_payload();
+        let mut chunks: Vec<Bytes> = Vec::new();
+        let mut size = 0;
+        let mut complete = false;
+        while size <= self.body_limit {
+            match payload.next().await {
+                Some(chunk) => {
+                    let chunk = chunk?;
+                    size += chunk.len();
+                    chunks.push(chunk);
+                }
+                None => {
+                    complete = true;
+                    break;
+                }
+            }
+        }
+
+        let token = if complete {
+            let body = chunks.concat();
+            std::str::from_utf8(&body)
+                .ok()
+                .and_then(|body| QString::from(body).get(&self.name).map(ToString::to_string))
+        } else {
+            None
+        };
+
+        // Put back whatever we read, followed by anything we didn't.
+        req.set_payload(Payload::Stream {
+            payload: Box::pin(stream::iter(chunks.into_iter().map(Ok)).chain(payload)),
+        });
+        Ok(token)
+    }
+}
+
+impl From<&str> for CsrfToken {
+    fn from(name: &str) -> Self {
+        Self::new(name)
+    }
+}
+
+impl fmt::Debug for CsrfToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CsrfToken")
+            .field("name", &self.name)
+            .field("cookie_name", &self.cookie_name)
+            .field("header_name", &self.header_name)
+            .field("body_limit", &self.body_limit)
+            .field("validator", &self.validator.as_ref().map(|_| "<function>"))
+            .finish()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The outcome of looking for and checking a CSRF token.
+pub(crate) enum CsrfCheck {
+    Valid,
+    Missing,
+    Invalid,
+}
+
+impl CsrfCheck {
+    /// Why to reject the request, and what to tell the client, if it has to
+    /// be rejected.
+    pub(crate) fn rejection(self) -> Option<(RejectionReason, &'static str)> {
+        match self {
+            Self::Valid => None,
+            Self::Missing => Some((RejectionReason::CsrfTokenMissing, "CSRF token is missing")),
+            Self::Invalid => Some((RejectionReason::CsrfTokenInvalid, "CSRF token is invalid")),
+        }
+    }
+}
+
+const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+
+/// Compare two strings without exiting early, so that the comparison doesn't
+/// leak how much of the token was correct.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq("token", "token"));
+        assert!(!constant_time_eq("token", "tokex"));
+        assert!(!constant_time_eq("token", "token2"));
+        assert!(!constant_time_eq("", "token"));
+    }
+}
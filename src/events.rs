@@ -0,0 +1,120 @@
+//! Events the middleware reports about the requests it reroutes or rejects,
+//! configured with [`QueryMethodBuilder::on_event`](crate::QueryMethodBuilder::on_event).
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use actix_web::http::Method;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+/// Something the middleware did with a request that had the method parameter.
+pub enum QueryMethodEvent {
+    /// The request was rerouted to another method.
+    Rewritten {
+        /// The method the request was sent with.
+        from: Method,
+        /// The method the request was rerouted to.
+        to: Method,
+        /// The path of the request.
+        path: String,
+    },
+    /// The request was rejected without being passed on to your server.
+    Rejected {
+        /// Why the request was rejected.
+        reason: RejectionReason,
+        /// The path of the request.
+        path: String,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+/// Why the middleware rejected a request.
+pub enum RejectionReason {
+    /// The method parameter is not a valid method.
+    BadMethodValue,
+    /// The request was sent with a method that can't be rerouted, and the
+    /// [`StrictModePolicy`](crate::StrictModePolicy) for it is to reject.
+    StrictMode,
+    /// The filter rejected the request.
+    FilterRejected,
+    /// A CSRF token is required, but the request didn't have one.
+    CsrfTokenMissing,
+    /// A CSRF token is required, but the request's token was not valid.
+    CsrfTokenInvalid,
+    /// Same origin is enforced, but the request had neither an `Origin` nor a
+    /// `Referer` header.
+    OriginMissing,
+    /// Same origin is enforced, but the request came from another origin.
+    CrossOrigin,
+}
+
+impl RejectionReason {
+    /// All the reasons, in the order of [`RejectionReason::index`].
+    #[cfg(feature = "metrics")]
+    pub(crate) const ALL: [RejectionReason; 7] = [
+        Self::BadMethodValue,
+        Self::StrictMode,
+        Self::FilterRejected,
+        Self::CsrfTokenMissing,
+        Self::CsrfTokenInvalid,
+        Self::OriginMissing,
+        Self::CrossOrigin,
+    ];
+
+    /// A short name for the reason, like `bad_method_value`, which works well
+    /// as a metrics label.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::BadMethodValue => "bad_method_value",
+            Self::StrictMode => "strict_mode",
+            Self::FilterRejected => "filter_rejected",
+            Self::CsrfTokenMissing => "csrf_token_missing",
+            Self::CsrfTokenInvalid => "csrf_token_invalid",
+            Self::OriginMissing => "origin_missing",
+            Self::CrossOrigin => "cross_origin",
+        }
+    }
+
+    /// The position of the reason in [`RejectionReason::ALL`].
+    #[cfg(feature = "metrics")]
+    pub(crate) fn index(&self) -> usize {
+        match self {
+            Self::BadMethodValue => 0,
+            Self::StrictMode => 1,
+            Self::FilterRejected => 2,
+            Self::CsrfTokenMissing => 3,
+            Self::CsrfTokenInvalid => 4,
+            Self::OriginMissing => 5,
+            Self::CrossOrigin => 6,
+        }
+    }
+}
+
+impl fmt::Display for RejectionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A function that gets called with every [`QueryMethodEvent`].
+pub type EventHandler = dyn Fn(QueryMethodEvent) + Send + Sync;
+
+#[derive(Clone)]
+/// An event handler that can be shared by all copies of the middleware.
+pub(crate) struct SharedEventHandler(pub(crate) Arc<EventHandler>);
+
+impl Deref for SharedEventHandler {
+    type Target = EventHandler;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+impl fmt::Debug for SharedEventHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<function>")
+    }
+}
@@ -0,0 +1,72 @@
+//! Deciding per request whether a request may be rerouted, configured with
+//! [`QueryMethodBuilder::filter`](crate::QueryMethodBuilder::filter).
+use std::fmt;
+use std::future::{ready, Future, Ready};
+use std::ops::Deref;
+use std::sync::Arc;
+
+use actix_web::dev::ServiceRequest;
+use actix_web::http::Method;
+use futures::future::{Either, LocalBoxFuture};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// What the middleware should do with a request that has the method parameter.
+pub enum OverrideDecision {
+    /// Reroute the request.
+    Allow,
+    /// Pass the request on to your server with its method unchanged.
+    Skip,
+    /// Reject the request with a 403 code response.
+    Reject,
+}
+
+/// The decision of a filter. A filter that decides right away doesn't need to
+/// box its decision.
+pub type OverrideDecisionFuture =
+    Either<Ready<OverrideDecision>, LocalBoxFuture<'static, OverrideDecision>>;
+
+/// The result of a filter given to
+/// [`QueryMethodBuilder::filter`](crate::QueryMethodBuilder::filter).
+///
+/// This is implemented for [`OverrideDecision`] itself, and for futures that
+/// resolve to an `OverrideDecision`, so the filter can be async.
+pub trait IntoOverrideDecision {
+    /// Turn this into a future that resolves to the decision.
+    fn into_decision(self) -> OverrideDecisionFuture;
+}
+
+impl IntoOverrideDecision for OverrideDecision {
+    fn into_decision(self) -> OverrideDecisionFuture {
+        Either::Left(ready(self))
+    }
+}
+
+impl<F> IntoOverrideDecision for F
+where
+    F: Future<Output = OverrideDecision> + 'static,
+{
+    fn into_decision(self) -> OverrideDecisionFuture {
+        Either::Right(Box::pin(self))
+    }
+}
+
+/// A filter that decides whether a request may be rerouted to a method.
+pub type OverrideFilter = dyn Fn(&ServiceRequest, &Method) -> OverrideDecisionFuture + Send + Sync;
+
+#[derive(Clone)]
+/// A filter that can be shared by all copies of the middleware.
+pub(crate) struct SharedFilter(pub(crate) Arc<OverrideFilter>);
+
+impl Deref for SharedFilter {
+    type Target = OverrideFilter;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+impl fmt::Debug for SharedFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<function>")
+    }
+}
@@ -23,21 +23,172 @@
 //! ```
 //!
 //! The middleware will strip off the `_method` query parameter when rerouting
-//! your request, so the rerouting is transparent to your server code.
+//! your request, so the rerouting is transparent to your server code. If your
+//! handler does need to know that the request was rerouted, for example for
+//! audit logs, you can extract the [`OriginalMethod`]:
+//!
+//! ```rs
+//! async fn delete_item(original: Option<OriginalMethod>) -> impl Responder {
+//!     if let Some(OriginalMethod(method)) = original {
+//!         // The request was sent as `method`, and rerouted to DELETE
+//!     }
+//!     // ...
+//! }
+//! ```
+//!
+//! If you want a route to only handle rerouted requests, you can add the
+//! [`QueryMethodApplied`] guard to it. If you want rerouted requests to some
+//! scopes or routes to keep the method they were sent with, you can wrap them
+//! with [`SkipQueryMethod`]. This can't stop the middleware from rejecting
+//! requests to them, for example for a method parameter that isn't a valid
+//! method. For endpoints like webhooks where `_method` may be plain data, use
+//! [`SkipQueryMethod::when`] around the middleware instead, which keeps the
+//! requests it picks away from the middleware before any checks.
+//!
+//! ```rs
+//! App::new()
+//!      .wrap(QueryMethod::default())
+//!      .wrap(SkipQueryMethod::when(|req: &ServiceRequest| {
+//!          req.match_pattern().as_deref() == Some("/webhooks/{provider}")
+//!      }))
+//!      .service(web::scope("/legacy").wrap(SkipQueryMethod))
+//!      // ...
+//! ```
 //!
 //! Note that this middleware only applies to `POST` requests. Any other request
 //! like `GET` or `HEAD` will not be changed, because it would risk opening the
 //! server up to XSRF attacks. Requests like `PUT` and `DELETE` are also not
 //! changed because the parameter was likely included accidentally. By default
 //! the middleware will allow these requests to continue to your server
-//! unchanged, but you can enable the `strict_mode` parameter to reject such
-//! requests.
+//! unchanged, but you can enable [`QueryMethodBuilder::strict_mode`] to reject
+//! such requests. If you need more control, you can pick a
+//! [`StrictModePolicy`] for all of these requests or just for some methods,
+//! for example to reject `GET` requests with `405 Method Not Allowed` and to
+//! strip the parameter off any other requests.
+//!
+//! ```rs
+//! QueryMethod::builder()
+//!     .strict_mode_policy(StrictModePolicy::StripAndContinue)
+//!     .strict_mode_policy_for(
+//!         Method::GET,
+//!         StrictModePolicy::Reject { status: StatusCode::METHOD_NOT_ALLOWED },
+//!     )
+//!     .build()
+//! ```
+//!
+//! To change any of the settings, create the middleware with
+//! [`QueryMethod::builder`]. The builder checks your settings when you call
+//! `build`, so mistakes like an empty parameter name are caught when your
+//! server starts.
+//!
+//! ```rs
+//! App::new()
+//!      .wrap(QueryMethod::builder().strict_mode(true).build().unwrap())
+//!      // ...
+//! ```
+//!
+//! Requests that aren't rerouted keep the method parameter by default. If you
+//! don't want the parameter to ever reach your handlers, query extractors, or
+//! anything that keys on the request URI like caches and analytics, you can
+//! enable [`QueryMethodBuilder::always_strip_parameter`] to remove it from
+//! every request.
+//!
+//! If you only want the middleware to apply to some of your routes, for
+//! example your HTML form routes but not your JSON API routes, you can limit
+//! it to some paths with [`QueryMethodBuilder::include_paths`] and
+//! [`QueryMethodBuilder::exclude_paths`]. Requests to other paths pass through the
+//! middleware untouched, even in strict mode.
+//!
+//! ```rs
+//! App::new()
+//!      .wrap(
+//!          QueryMethod::builder()
+//!              .include_paths(["/admin/**"])
+//!              .exclude_paths(["/admin/api"])
+//!              .build()
+//!              .unwrap(),
+//!      )
+//!      // ...
+//! ```
+//!
+//! Because the method parameter lets a plain HTML form send `PUT` or `DELETE`
+//! requests, it also lets another site's form do the same. You can have the
+//! middleware require a CSRF token before rerouting a request with
+//! [`QueryMethodBuilder::require_csrf_token`]. By default the token has to match a
+//! cookie with the same name, and the token can be passed in the query string,
+//! the `X-CSRF-Token` header, or the form body.
+//!
+//! ```html
+//! <form method="post" action="/path/to/endpoint?_method=DELETE">
+//!   <input type="hidden" name="csrf_token" value="..." />
+//!   <input type="submit" value="Delete this item" />
+//! </form>
+//! ```
+//!
+//! ```rs
+//! App::new()
+//!      .wrap(QueryMethod::builder().require_csrf_token("csrf_token").build().unwrap())
+//!      // ...
+//! ```
+//!
+//! A lighter-weight alternative is [`QueryMethodBuilder::enforce_same_origin`],
+//! which only reroutes requests whose `Origin` or `Referer` header shows they
+//! came from your own site, or from the origins you allow with
+//! [`QueryMethodBuilder::allowed_origins`].
+//!
+//! If you need to decide per request whether a request may be rerouted, for
+//! example only for logged in users, you can add a filter with
+//! [`QueryMethodBuilder::filter`]. The filter runs before the request is
+//! changed, and can allow the rerouting, skip it, or reject the request.
+//!
+//! ```rs
+//! QueryMethod::builder()
+//!     .filter(|req: &ServiceRequest, _method: &Method| {
+//!         if req.headers().contains_key("X-Allow-Override") {
+//!             OverrideDecision::Allow
+//!         } else {
+//!             OverrideDecision::Reject
+//!         }
+//!     })
+//!     .build()
+//! ```
+//!
+//! The method parameter is case-insensitive, so `?_method=delete` is rerouted
+//! to `DELETE`, and it may be percent-encoded like `?_method=%44ELETE`. You can
+//! also add aliases with [`QueryMethodBuilder::method_alias`] if you'd like to
+//! use friendlier names in your forms, like `?_method=remove`.
+//!
+//! If you do need to reroute requests sent with other methods, for example
+//! from a client that can only send `GET` requests, you can pick the methods
+//! with [`QueryMethodBuilder::allowed_original_methods`]. This opens up the
+//! risks above, so it's best to only do it for the paths that need it with
+//! [`QueryMethodBuilder::allowed_original_methods_for_paths`].
+//!
+//! ```rs
+//! QueryMethod::builder()
+//!     .allowed_original_methods_for_paths(["/admin/cache"], [Method::POST, Method::GET])
+//!     .enforce_same_origin()
+//!     .build()
+//! ```
 //!
 //! The middleware will also reject any request where the method parameter
 //! specifies an invalid method that Actix Web doesn't accept. You *can* use
 //! custom HTTP methods like `LIST`, but not `LIST:ITEMS`. See the
 //! [HTTP spec for details](https://www.w3.org/Protocols/HTTP/1.1/draft-ietf-http-v11-spec-01#Method).
 //!
+//! If you'd like to keep track of what the middleware does, you can have it
+//! call a function with a [`QueryMethodEvent`] whenever it reroutes or rejects
+//! a request using [`QueryMethodBuilder::on_event`]. With the `metrics`
+//! feature enabled, you can also have it count these events with a
+#![cfg_attr(
+    feature = "metrics",
+    doc = "[`QueryMethodMetrics`] handle, which can render the counts for Prometheus."
+)]
+#![cfg_attr(
+    not(feature = "metrics"),
+    doc = "`QueryMethodMetrics` handle, which can render the counts for Prometheus."
+)]
+//!
 //! This middleware uses [tracing](https://docs.rs/tracing/latest/tracing/) for
 //! logging. It will log warning events for bad requests (for example, GET
 //! request with method parameter), and will log debug events for good requests
@@ -47,22 +198,53 @@
 //!
 //! ```toml
 //! # To use `log` for logging
-//! actix-web-query-method-middleware = { version = "1.0", default-features = false, features = ["logging_log"] }
+//! actix-web-query-method-middleware = { version = "1.1", default-features = false, features = ["logging_log"] }
 //! # To disable logging entirely
-//! actix-web-query-method-middleware = { version = "1.0", default-features = false }
+//! actix-web-query-method-middleware = { version = "1.1", default-features = false }
+//! # To count rerouted and rejected requests
+//! actix-web-query-method-middleware = { version = "1.1", features = ["metrics"] }
 //! ```
+use std::cell::Cell;
+use std::collections::HashMap;
 use std::future::{ready, Ready};
 use std::rc::Rc;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use actix_web::body::EitherBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
 use actix_web::dev::{Service, Transform};
-use actix_web::dev::{ServiceRequest, ServiceResponse};
-use actix_web::http::{uri::PathAndQuery, Method, Uri};
-use actix_web::{Error, HttpResponse};
-use futures::future::LocalBoxFuture;
+use actix_web::error::ErrorBadRequest;
+use actix_web::guard::{Guard, GuardContext};
+use actix_web::http::{header, uri::PathAndQuery, Method, Uri};
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest, HttpResponse};
+use futures::future::{Either, LocalBoxFuture, MapOk, TryFutureExt};
 use qstring::QString;
 
+mod builder;
+mod csrf;
+mod events;
+mod filter;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod origin;
+mod path_pattern;
+mod skip;
+mod strict_mode;
+pub use builder::{ConfigError, QueryMethodBuilder};
+pub use csrf::{CsrfToken, CsrfValidator};
+use events::SharedEventHandler;
+pub use events::{EventHandler, QueryMethodEvent, RejectionReason};
+use filter::SharedFilter;
+pub use filter::{IntoOverrideDecision, OverrideDecision, OverrideDecisionFuture, OverrideFilter};
+#[cfg(feature = "metrics")]
+pub use metrics::QueryMethodMetrics;
+use origin::SameOrigin;
+use path_pattern::PathPattern;
+use skip::{OriginalUri, RerouteUndone};
+pub use skip::{SkipQueryMethod, SkipQueryMethodMiddleware, SkipQueryMethodWhen};
+pub use strict_mode::StrictModePolicy;
+
 #[derive(Clone, Debug)]
 /// A middleware to pick HTTP method (PUT, DELETE, ...) with a query parameter.
 ///
@@ -71,14 +253,42 @@ use qstring::QString;
 /// method.
 pub struct QueryMethod {
     parameter_name: String,
-    strict_mode: bool,
+    strict_mode: StrictModePolicy,
+    strict_mode_methods: HashMap<Method, StrictModePolicy>,
+    include_paths: Vec<PathPattern>,
+    exclude_paths: Vec<PathPattern>,
+    csrf_token: Option<Arc<CsrfToken>>,
+    always_strip_parameter: bool,
+    normalize_method_case: bool,
+    method_aliases: HashMap<String, Method>,
+    filter: Option<SharedFilter>,
+    same_origin: Option<SameOrigin>,
+    on_event: Option<SharedEventHandler>,
+    #[cfg(feature = "metrics")]
+    metrics: Option<QueryMethodMetrics>,
+    allowed_original_methods: Vec<Method>,
+    allowed_original_methods_for_paths: Vec<(Vec<PathPattern>, Vec<Method>)>,
 }
 
 impl Default for QueryMethod {
     fn default() -> Self {
         Self {
             parameter_name: "_method".to_string(),
-            strict_mode: false,
+            strict_mode: StrictModePolicy::PassThrough,
+            strict_mode_methods: HashMap::new(),
+            include_paths: Vec::new(),
+            exclude_paths: Vec::new(),
+            csrf_token: None,
+            always_strip_parameter: false,
+            normalize_method_case: true,
+            method_aliases: HashMap::new(),
+            filter: None,
+            same_origin: None,
+            on_event: None,
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            allowed_original_methods: vec![Method::POST],
+            allowed_original_methods_for_paths: Vec::new(),
         }
     }
 }
@@ -90,11 +300,19 @@ impl QueryMethod {
         Self::default()
     }
 
+    /// Start building the middleware with custom settings. The builder checks
+    /// the settings when you call [`QueryMethodBuilder::build`].
+    #[must_use]
+    pub fn builder() -> QueryMethodBuilder {
+        QueryMethodBuilder::new()
+    }
+
     /// The parameter name to use. By default this is `_method`, meaning that
     /// you need to send your request like `/path?_method=POST` to use this
     /// middleware. If you happen to already use `_method` in your application,
     /// you can override the parameter name used here to pick something else.
     #[must_use]
+    #[deprecated(since = "1.1.0", note = "use `QueryMethod::builder()` instead")]
     pub fn parameter_name(&mut self, name: &str) -> Self {
         self.parameter_name = name.to_string();
         self.clone()
@@ -103,24 +321,93 @@ impl QueryMethod {
     /// Disabled by default. When enabled, the middleware will respond to
     /// non-POST requests by rejecting them with a 400 code response.
     #[must_use]
+    #[deprecated(since = "1.1.0", note = "use `QueryMethod::builder()` instead")]
     pub fn enable_strict_mode(&mut self) -> Self {
-        self.strict_mode = true;
+        self.strict_mode = StrictModePolicy::STRICT;
         self.clone()
     }
 
     /// Disabled by default. When disabled, the middleware will allow non-POST
-    /// requests that have
+    /// requests that have the method parameter to continue to your server
+    /// unchanged.
     #[must_use]
+    #[deprecated(since = "1.1.0", note = "use `QueryMethod::builder()` instead")]
     pub fn disable_strict_mode(&mut self) -> Self {
-        self.strict_mode = false;
+        self.strict_mode = StrictModePolicy::PassThrough;
         self.clone()
     }
+
+    /// Find the method the parameter value refers to, if any. The value has
+    /// already been percent-decoded when the query string was parsed.
+    fn parse_method(&self, value: &str) -> Option<Method> {
+        if let Some(method) = self.method_aliases.get(&value.to_ascii_lowercase()) {
+            return Some(method.clone());
+        }
+        if self.normalize_method_case {
+            Method::from_str(&value.to_ascii_uppercase()).ok()
+        } else {
+            Method::from_str(value).ok()
+        }
+    }
+
+    /// What to do with a request that has the method parameter, but can't be
+    /// rerouted because of its method.
+    fn strict_mode_policy(&self, method: &Method) -> StrictModePolicy {
+        self.strict_mode_methods
+            .get(method)
+            .copied()
+            .unwrap_or(self.strict_mode)
+    }
+
+    /// The methods that requests to this path may be rerouted from. Like
+    /// [`QueryMethod::applies_to_path`], this needs the percent-decoded path.
+    fn allowed_original_methods(&self, path: &str) -> &[Method] {
+        self.allowed_original_methods_for_paths
+            .iter()
+            .find(|(patterns, _)| patterns.iter().any(|p| p.matches(path)))
+            .map_or(&self.allowed_original_methods, |(_, methods)| methods)
+    }
+
+    /// Whether there is an event handler or metrics to report events to.
+    fn wants_events(&self) -> bool {
+        #[cfg(feature = "metrics")]
+        let has_metrics = self.metrics.is_some();
+        #[cfg(not(feature = "metrics"))]
+        let has_metrics = false;
+        self.on_event.is_some() || has_metrics
+    }
+
+    /// Report an event to the event handler and the metrics, if there are
+    /// any. The event is only created if someone will see it.
+    fn emit<F: FnOnce() -> QueryMethodEvent>(&self, event: F) {
+        if !self.wants_events() {
+            return;
+        }
+
+        let event = event();
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            metrics.record(&event);
+        }
+        if let Some(on_event) = &self.on_event {
+            on_event(event);
+        }
+    }
+
+    /// Whether the middleware should look at requests to this path at all. The
+    /// path has to be percent-decoded like the router does it, otherwise
+    /// `/ap%69` would get past a `/api` exclusion and still reach `/api`.
+    fn applies_to_path(&self, path: &str) -> bool {
+        (self.include_paths.is_empty() || self.include_paths.iter().any(|p| p.matches(path)))
+            && !self.exclude_paths.iter().any(|p| p.matches(path))
+    }
 }
 
 impl<S, B> Transform<S, ServiceRequest> for QueryMethod
 where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
+    B: 'static,
 {
     type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
@@ -131,82 +418,345 @@ where
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(QueryMethodMiddleware {
             service: Rc::new(service),
-            options: self.clone(),
+            options: Rc::new(self.clone()),
         }))
     }
 }
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// The method a request was originally sent with, before the middleware
+/// rerouted it.
+///
+/// The middleware adds this to the request extensions whenever it reroutes a
+/// request. You can use it as an extractor in your handlers, typically as
+/// `Option<OriginalMethod>` since requests that were not rerouted won't have
+/// it. Extracting it without the `Option` will reject requests that were not
+/// rerouted with a 400 code response.
+pub struct OriginalMethod(pub Method);
+
+impl FromRequest for OriginalMethod {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<OriginalMethod>()
+                .cloned()
+                .ok_or_else(|| ErrorBadRequest("Request method was not rerouted")),
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// A route guard that only matches requests the middleware rerouted.
+///
+/// ```rs
+/// web::resource("/item")
+///     // Only for forms, not for API clients sending real DELETE requests
+///     .route(web::delete().guard(QueryMethodApplied).to(delete_item_from_form))
+/// ```
+pub struct QueryMethodApplied;
+
+impl Guard for QueryMethodApplied {
+    fn check(&self, ctx: &GuardContext<'_>) -> bool {
+        ctx.req_data().contains::<OriginalMethod>()
+    }
+}
+
 pub struct QueryMethodMiddleware<S> {
     service: Rc<S>,
-    options: QueryMethod,
+    options: Rc<QueryMethod>,
 }
 
 /// Drop a parameter from the query string, if any. Returns a new query string.
-fn query_string_drop(query: QString, drop: &str) -> QString {
-    QString::new(
-        query
-            .into_iter()
-            .filter(|(k, _)| k.ne(drop))
-            .collect::<Vec<(String, String)>>(),
-    )
+/// The rest of the query string is kept exactly as it was sent, without
+/// decoding or re-encoding any of the other parameters.
+fn query_string_drop(query: &str, drop: &str) -> String {
+    query
+        .split('&')
+        .filter(|pair| !query_pair_is(pair, drop))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Check if the query string has the parameter. This doesn't allocate, unless
+/// a parameter name in the query string is percent-encoded.
+fn query_string_has(query: &str, name: &str) -> bool {
+    !query.is_empty() && query.split('&').any(|pair| query_pair_is(pair, name))
+}
+
+/// Check if a `key=value` pair from the query string is for this parameter.
+fn query_pair_is(pair: &str, name: &str) -> bool {
+    let key = pair.split_once('=').map_or(pair, |(key, _)| key);
+    key == name || (key.contains('%') && QString::from(pair).has(name))
+}
+
+/// A `Rewritten` event that is reported once the request has been handled,
+/// because a [`SkipQueryMethod`] further in may still undo the rerouting.
+struct PendingRewritten {
+    event: QueryMethodEvent,
+    undone: Rc<Cell<bool>>,
+}
+
+impl PendingRewritten {
+    fn report(self, options: &QueryMethod) {
+        if !self.undone.get() {
+            options.emit(|| self.event);
+        }
+    }
+}
+
+/// Change the method of the request, and drop the method parameter from the
+/// query string. Returns the event to report once the request has been
+/// handled, if there is anyone to report it to.
+fn reroute(
+    options: &QueryMethod,
+    req: &mut ServiceRequest,
+    new_method: Method,
+) -> Option<PendingRewritten> {
+    #[cfg(feature = "logging_tracing")]
+    tracing::debug!(
+        new_method = new_method.as_str(),
+        path = req.path(),
+        original_method = req.method().as_str(),
+        "Rerouting request method"
+    );
+    #[cfg(feature = "logging_log")]
+    log::debug!(
+        "Rerouting request for {} to method {}",
+        req.path(),
+        new_method.as_str()
+    );
+    let pending = options.wants_events().then(|| {
+        let undone = Rc::new(Cell::new(false));
+        req.extensions_mut().insert(RerouteUndone(undone.clone()));
+        PendingRewritten {
+            event: QueryMethodEvent::Rewritten {
+                from: req.method().clone(),
+                to: new_method.clone(),
+                path: req.path().to_string(),
+            },
+            undone,
+        }
+    });
+    let original_method = OriginalMethod(req.method().clone());
+    req.extensions_mut().insert(original_method);
+    req.head_mut().method = new_method;
+    strip_parameter(req, &options.parameter_name);
+    pending
+}
+
+/// Pass a rerouted request on to the server, then report the rerouting.
+async fn call_rerouted<S, B>(
+    service: &S,
+    options: &QueryMethod,
+    req: ServiceRequest,
+    pending: Option<PendingRewritten>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let response = service.call(req).await;
+    if let Some(pending) = pending {
+        pending.report(options);
+    }
+    response.map(ServiceResponse::map_into_left_body)
+}
+
+/// Drop the method parameter from the query string of the request.
+fn strip_parameter(req: &mut ServiceRequest, parameter_name: &str) {
+    let original_uri = req.head().uri.clone();
+    let mut uri_parts = original_uri.clone().into_parts();
+    if !req.extensions().contains::<OriginalUri>() {
+        req.extensions_mut().insert(OriginalUri(original_uri));
+    }
+    let query = query_string_drop(req.query_string(), parameter_name);
+    let separator = if query.is_empty() { "" } else { "?" };
+    uri_parts.path_and_query = Some(
+        PathAndQuery::from_str(&format!("{}{}{}", req.path(), separator, query))
+            // This unwrap is safe, since the string we're
+            // making the path an query out of is the path and
+            // query the server had already parsed and accepted.
+            // Our modification here should not break things,
+            // and we test for it as well.
+            .unwrap(),
+    );
+    // This unwrap is also safe since we're just
+    // reconstructing the uri from it's own old parts.
+    req.head_mut().uri = Uri::from_parts(uri_parts).unwrap();
+}
+
+/// Run the checks that need the filter's decision or the request body, and
+/// reroute the request if they all pass.
+async fn check_and_reroute<S, B>(
+    service: Rc<S>,
+    options: Rc<QueryMethod>,
+    mut req: ServiceRequest,
+    new_method: Method,
+    decision: Option<OverrideDecisionFuture>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    if let Some(decision) = decision {
+        match decision.await {
+            OverrideDecision::Allow => {}
+            OverrideDecision::Skip => {
+                if options.always_strip_parameter {
+                    strip_parameter(&mut req, &options.parameter_name);
+                }
+                return service
+                    .call(req)
+                    .await
+                    .map(ServiceResponse::map_into_left_body);
+            }
+            OverrideDecision::Reject => {
+                let message = format!(
+                    "Method {} is not allowed for this request",
+                    new_method.as_str()
+                );
+                return reject_check(&options, req, RejectionReason::FilterRejected, message);
+            }
+        }
+    }
+    if let Some(same_origin) = &options.same_origin {
+        if let Some((reason, message)) = same_origin.check(&req).rejection() {
+            return reject_check(&options, req, reason, message);
+        }
+    }
+    if let Some(csrf_token) = &options.csrf_token {
+        if let Some((reason, message)) = csrf_token.check(&mut req).await?.rejection() {
+            return reject_check(&options, req, reason, message);
+        }
+    }
+    let pending = reroute(&options, &mut req, new_method);
+    call_rerouted(service.as_ref(), &options, req, pending).await
+}
+
+/// Respond with a 403 to a request that failed one of the checks before
+/// rerouting.
+fn reject_check<B, M: Into<String>>(
+    options: &QueryMethod,
+    req: ServiceRequest,
+    reason: RejectionReason,
+    message: M,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    #[cfg(feature = "logging_tracing")]
+    tracing::warn!(
+        path = req.path(),
+        reason = reason.as_str(),
+        "Rejected a request before rerouting it"
+    );
+    #[cfg(feature = "logging_log")]
+    log::warn!(
+        "Rejected a request for {} before rerouting it: {}",
+        req.path(),
+        reason
+    );
+    let response = HttpResponse::Forbidden().body(message.into());
+    reject(options, req, reason, response)
+}
+
+/// Respond to the request without passing it on to the server.
+fn reject<B>(
+    options: &QueryMethod,
+    req: ServiceRequest,
+    reason: RejectionReason,
+    response: HttpResponse,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    options.emit(|| QueryMethodEvent::Rejected {
+        reason,
+        path: req.path().to_string(),
+    });
+    let (request, _) = req.into_parts();
+    Ok(ServiceResponse::new(
+        request,
+        response.map_into_right_body(),
+    ))
+}
+
+/// The future of a request the middleware passes on to the server without
+/// having to wait for anything itself.
+pub type PassThroughFuture<F, B> =
+    MapOk<F, fn(ServiceResponse<B>) -> ServiceResponse<EitherBody<B>>>;
+
+/// The future of a request going through the middleware.
+pub type QueryMethodFuture<F, B> = Either<
+    PassThroughFuture<F, B>,
+    LocalBoxFuture<'static, Result<ServiceResponse<EitherBody<B>>, Error>>,
+>;
+
+impl<S, B> QueryMethodMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    /// Pass the request on to the server, without boxing the future.
+    fn pass_through(&self, req: ServiceRequest) -> QueryMethodFuture<S::Future, B> {
+        Either::Left(
+            self.service
+                .call(req)
+                .map_ok(ServiceResponse::map_into_left_body as fn(_) -> _),
+        )
+    }
 }
 
 impl<S, B> Service<ServiceRequest> for QueryMethodMiddleware<S>
 where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
+    B: 'static,
 {
     type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
-    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+    type Future = QueryMethodFuture<S::Future, B>;
 
     actix_service::forward_ready!(service);
 
     fn call(&self, mut req: ServiceRequest) -> Self::Future {
-        let uri = req.head().uri.clone();
-        let mut uri_parts = uri.into_parts();
-        let (path, query_string) = uri_parts.path_and_query.map_or_else(
-            || ("".to_string(), "".to_string()),
-            |pq| {
-                (
-                    pq.path().to_string(),
-                    pq.query()
-                        .map_or_else(|| "".to_string(), ToString::to_string),
-                )
-            },
-        );
-        let query = QString::from(query_string.as_str());
+        // Most requests don't have the method parameter, so check for it
+        // without parsing the query string or allocating anything first.
+        if !query_string_has(req.query_string(), &self.options.parameter_name)
+            || !self.options.applies_to_path(req.match_info().as_str())
+            || req.extensions().contains::<SkipQueryMethod>()
+        {
+            return self.pass_through(req);
+        }
+
+        let query = QString::from(req.query_string());
 
-        if let Some(value) = query.clone().get(&self.options.parameter_name) {
+        if let Some(value) = query.get(&self.options.parameter_name) {
             // Method parameter specified, try to redirect
             let original_method = req.method();
-            if original_method.eq(&Method::POST) {
-                #[cfg(feature = "logging_tracing")]
-                tracing::debug!(
-                    parameter_value = value,
-                    path = req.path(),
-                    original_method = original_method.as_str(),
-                    "Rerouting request method"
-                );
-                #[cfg(feature = "logging_log")]
-                log::debug!("Rerouting request for {} to method {}", req.path(), value);
-                if let Ok(new_method) = Method::from_str(value) {
-                    req.head_mut().method = new_method;
-                    uri_parts.path_and_query = Some(
-                        PathAndQuery::from_str(&format!(
-                            "{}{}",
-                            path,
-                            query_string_drop(query, &self.options.parameter_name)
-                        ))
-                        // This unwrap is safe, since the string we're
-                        // making the path an query out of is the path and
-                        // query the server had already parsed and accepted.
-                        // Our modification here should not break things,
-                        // and we test for it as well.
-                        .unwrap(),
-                    );
-                    // This unwrap is also safe since we're just
-                    // reconstructing the uri from it's own old parts.
-                    req.head_mut().uri = Uri::from_parts(uri_parts).unwrap();
+            let allowed_original_methods = self
+                .options
+                .allowed_original_methods(req.match_info().as_str());
+            if allowed_original_methods.contains(original_method) {
+                if let Some(new_method) = self.options.parse_method(value) {
+                    let decision = self
+                        .options
+                        .filter
+                        .as_ref()
+                        .map(|filter| filter(&req, &new_method));
+                    if decision.is_some()
+                        || self.options.same_origin.is_some()
+                        || self.options.csrf_token.is_some()
+                    {
+                        return Either::Right(Box::pin(check_and_reroute(
+                            self.service.clone(),
+                            self.options.clone(),
+                            req,
+                            new_method,
+                            decision,
+                        )));
+                    }
+                    if let Some(pending) = reroute(&self.options, &mut req, new_method) {
+                        let service = self.service.clone();
+                        let options = self.options.clone();
+                        return Either::Right(Box::pin(async move {
+                            call_rerouted(service.as_ref(), &options, req, Some(pending)).await
+                        }));
+                    }
                 } else {
                     #[cfg(feature = "logging_tracing")]
                     tracing::warn!(
@@ -222,14 +772,14 @@ where
                         value,
                         req.path(),
                     );
-                    let value = value.to_string();
-                    return Box::pin(async move {
-                        let response = HttpResponse::BadRequest()
-                            .body(format!("Method query parameter value {} is bad", value))
-                            .map_into_right_body();
-                        let (request, _) = req.into_parts();
-                        Ok(ServiceResponse::new(request, response))
-                    });
+                    let response = HttpResponse::BadRequest()
+                        .body(format!("Method query parameter value {} is bad", value));
+                    return Either::Right(Box::pin(ready(reject(
+                        &self.options,
+                        req,
+                        RejectionReason::BadMethodValue,
+                        response,
+                    ))));
                 }
             } else {
                 #[cfg(feature = "logging_tracing")]
@@ -238,7 +788,7 @@ where
                     parameter_value = value,
                     path = req.path(),
                     original_method = original_method.as_str(),
-                    "Received a non-POST request with the method query parameter"
+                    "Received a request with the method query parameter that can't be rerouted"
                 );
                 #[cfg(feature = "logging_log")]
                 log::warn!(
@@ -246,29 +796,39 @@ where
                     original_method.as_str(),
                     req.path(),
                 );
-                if self.options.strict_mode {
-                    let original_method = original_method.clone();
-                    return Box::pin(async move {
-                        let response = HttpResponse::BadRequest()
+                match self.options.strict_mode_policy(original_method) {
+                    StrictModePolicy::Reject { status } => {
+                        let allow = allowed_original_methods
+                            .iter()
+                            .map(Method::as_str)
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        let response = HttpResponse::build(status)
+                            .insert_header((header::ALLOW, allow))
                             .body(format!(
                                 "Method {} can not be rerouted with a query parameter",
                                 original_method.as_str()
-                            ))
-                            .map_into_right_body();
-                        let (request, _) = req.into_parts();
-                        Ok(ServiceResponse::new(request, response))
-                    });
+                            ));
+                        return Either::Right(Box::pin(ready(reject(
+                            &self.options,
+                            req,
+                            RejectionReason::StrictMode,
+                            response,
+                        ))));
+                    }
+                    StrictModePolicy::StripAndContinue => {
+                        strip_parameter(&mut req, &self.options.parameter_name);
+                    }
+                    StrictModePolicy::PassThrough => {
+                        if self.options.always_strip_parameter {
+                            strip_parameter(&mut req, &self.options.parameter_name);
+                        }
+                    }
                 }
             }
         }
 
-        let service = self.service.clone();
-        Box::pin(async move {
-            service
-                .call(req)
-                .await
-                .map(ServiceResponse::map_into_left_body)
-        })
+        self.pass_through(req)
     }
 }
 
@@ -276,7 +836,10 @@ where
 mod tests {
     use super::*;
     use actix_service::ServiceFactory;
-    use actix_web::{body::MessageBody, test, web, App, HttpRequest};
+    // Only the module, so that `#[test]` is still the built-in test attribute.
+    use actix_web::test::{self};
+    use actix_web::{body::MessageBody, cookie::Cookie, http::StatusCode, web, App, HttpRequest};
+    use std::sync::Mutex;
 
     fn setup_test_app() -> App<
         impl ServiceFactory<
@@ -321,6 +884,43 @@ mod tests {
         assert_eq!(resp_text, "PUT ", "POST request rerouted to PUT");
     }
 
+    #[test_log::test(actix_web::test)]
+    async fn test_post_rerouted_keeps_other_query_parameters() {
+        let app = test::init_service(setup_test_app()).await;
+        let req = test::TestRequest::post()
+            .uri("/?id=1&_method=PUT&name=item")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        let resp_text = String::from_utf8_lossy(&resp[..]);
+        assert_eq!(
+            resp_text, "PUT id=1&name=item",
+            "POST request rerouted to PUT"
+        );
+    }
+
+    #[test]
+    fn test_query_string_has_parameter() {
+        assert!(query_string_has("_method=PUT", "_method"));
+        assert!(query_string_has("id=1&_method=PUT", "_method"));
+        assert!(query_string_has("id=1&_method", "_method"));
+        assert!(query_string_has("id=1&%5Fmethod=PUT", "_method"));
+        assert!(!query_string_has("", "_method"));
+        assert!(!query_string_has("method=PUT", "_method"));
+        assert!(!query_string_has("id=_method", "_method"));
+        assert!(!query_string_has("_method_2=PUT", "_method"));
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_post_rerouted_with_percent_encoded_parameter_name() {
+        let app = test::init_service(setup_test_app()).await;
+        let req = test::TestRequest::post()
+            .uri("/?%5Fmethod=PUT")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        let resp_text = String::from_utf8_lossy(&resp[..]);
+        assert_eq!(resp_text, "PUT ", "POST request rerouted to PUT");
+    }
+
     #[test_log::test(actix_web::test)]
     async fn test_post_not_rerouted_with_query_missing() {
         let app = test::init_service(setup_test_app()).await;
@@ -365,7 +965,7 @@ mod tests {
     async fn test_get_request_failed_in_strict_mode() {
         let app = test::init_service(
             App::new()
-                .wrap(QueryMethod::new().enable_strict_mode())
+                .wrap(QueryMethod::builder().strict_mode(true).build().unwrap())
                 .route("/", web::get().to(|| async { "GET" }))
                 .route("/", web::post().to(|| async { "POST" }))
                 .route("/", web::put().to(|| async { "PUT" })),
@@ -380,7 +980,12 @@ mod tests {
     async fn test_post_rerouted_with_nondefault_parameter_name() {
         let app = test::init_service(
             App::new()
-                .wrap(QueryMethod::new().parameter_name("_my_hidden_method"))
+                .wrap(
+                    QueryMethod::builder()
+                        .parameter_name("_my_hidden_method")
+                        .build()
+                        .unwrap(),
+                )
                 .route("/", web::get().to(|| async { "GET" }))
                 .route("/", web::post().to(|| async { "POST" }))
                 .route("/", web::put().to(|| async { "PUT" })),
@@ -398,7 +1003,12 @@ mod tests {
     async fn test_post_not_rerouted_with_nondefault_parameter_name_and_different_query() {
         let app = test::init_service(
             App::new()
-                .wrap(QueryMethod::new().parameter_name("_my_hidden_method"))
+                .wrap(
+                    QueryMethod::builder()
+                        .parameter_name("_my_hidden_method")
+                        .build()
+                        .unwrap(),
+                )
                 .route("/", web::get().to(|| async { "GET" }))
                 .route("/", web::post().to(|| async { "POST" }))
                 .route("/", web::put().to(|| async { "PUT" })),
@@ -412,6 +1022,34 @@ mod tests {
         assert_eq!(resp_text, "POST", "not rerouted");
     }
 
+    #[test_log::test(actix_web::test)]
+    #[allow(deprecated)]
+    async fn test_deprecated_setters_still_configure_middleware() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    QueryMethod::new()
+                        .parameter_name("_my_hidden_method")
+                        .enable_strict_mode(),
+                )
+                .route("/", web::get().to(|| async { "GET" }))
+                .route("/", web::post().to(|| async { "POST" }))
+                .route("/", web::put().to(|| async { "PUT" })),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/?_my_hidden_method=PUT")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"PUT", "POST request rerouted to PUT");
+
+        let req = test::TestRequest::get()
+            .uri("/?_my_hidden_method=PUT")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400, "Request failed in strict mode");
+    }
+
     #[test_log::test(actix_web::test)]
     async fn test_post_reroutes_with_custom_method() {
         let app = test::init_service(
@@ -440,4 +1078,1063 @@ mod tests {
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), 400, "Bad method value is rejected");
     }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_original_method_extracted_when_rerouted() {
+        let app = test::init_service(App::new().wrap(QueryMethod::new()).route(
+            "/",
+            web::put().to(|original: Option<OriginalMethod>| async move {
+                format!("PUT {:?}", original.map(|OriginalMethod(method)| method))
+            }),
+        ))
+        .await;
+        let req = test::TestRequest::post().uri("/?_method=PUT").to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        let resp_text = String::from_utf8_lossy(&resp[..]);
+        assert_eq!(resp_text, "PUT Some(POST)", "original method is available");
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_original_method_missing_when_not_rerouted() {
+        let app = test::init_service(
+            App::new()
+                .wrap(QueryMethod::new())
+                .route(
+                    "/",
+                    web::put().to(|original: Option<OriginalMethod>| async move {
+                        format!("PUT {:?}", original.map(|OriginalMethod(method)| method))
+                    }),
+                )
+                .route(
+                    "/required",
+                    web::put().to(|_: OriginalMethod| async { "PUT" }),
+                ),
+        )
+        .await;
+        let req = test::TestRequest::put().uri("/").to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        let resp_text = String::from_utf8_lossy(&resp[..]);
+        assert_eq!(resp_text, "PUT None", "request was not rerouted");
+
+        let req = test::TestRequest::put().uri("/required").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400, "required original method is missing");
+    }
+
+    fn setup_path_scoped_test_app(
+        options: QueryMethod,
+    ) -> App<
+        impl ServiceFactory<
+            ServiceRequest,
+            Response = ServiceResponse<impl MessageBody>,
+            Config = (),
+            InitError = (),
+            Error = Error,
+        >,
+    > {
+        App::new()
+            .wrap(options)
+            .route("/admin/items", web::post().to(|| async { "POST" }))
+            .route("/admin/items", web::put().to(|| async { "PUT" }))
+            .route("/admin/items", web::get().to(|| async { "GET" }))
+            .route("/api/items", web::post().to(|| async { "POST" }))
+            .route("/api/items", web::put().to(|| async { "PUT" }))
+            .route("/api/items", web::get().to(|| async { "GET" }))
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_post_rerouted_only_on_included_paths() {
+        let app = test::init_service(setup_path_scoped_test_app(
+            QueryMethod::builder()
+                .include_paths(["/admin/**"])
+                .build()
+                .unwrap(),
+        ))
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/admin/items?_method=PUT")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"PUT", "included path is rerouted");
+
+        let req = test::TestRequest::post()
+            .uri("/api/items?_method=PUT")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"POST", "other path is not rerouted");
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_post_not_rerouted_on_excluded_paths() {
+        let app = test::init_service(setup_path_scoped_test_app(
+            QueryMethod::builder()
+                .exclude_paths(["/api"])
+                .build()
+                .unwrap(),
+        ))
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/admin/items?_method=PUT")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"PUT", "other path is rerouted");
+
+        let req = test::TestRequest::post()
+            .uri("/api/items?_method=PUT")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"POST", "excluded path is not rerouted");
+
+        let req = test::TestRequest::post()
+            .uri("/ap%69/items?_method=PUT")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"POST", "encoded excluded path is not rerouted");
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_excluded_paths_pass_through_in_strict_mode() {
+        let app = test::init_service(setup_path_scoped_test_app(
+            QueryMethod::builder()
+                .strict_mode(true)
+                .include_paths(["/admin/**"])
+                .build()
+                .unwrap(),
+        ))
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/api/items?_method=PUT")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200, "excluded path is not rejected");
+
+        let req = test::TestRequest::get()
+            .uri("/admin/items?_method=PUT")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400, "included path is rejected");
+    }
+
+    fn setup_csrf_test_app(
+        csrf_token: CsrfToken,
+    ) -> App<
+        impl ServiceFactory<
+            ServiceRequest,
+            Response = ServiceResponse<impl MessageBody>,
+            Config = (),
+            InitError = (),
+            Error = Error,
+        >,
+    > {
+        App::new()
+            .wrap(
+                QueryMethod::builder()
+                    .require_csrf_token(csrf_token)
+                    .build()
+                    .unwrap(),
+            )
+            .route("/", web::post().to(|| async { "POST" }))
+            .route(
+                "/",
+                web::put().to(|body: String| async move { format!("PUT {}", body) }),
+            )
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_post_rejected_without_csrf_token() {
+        let app = test::init_service(setup_csrf_test_app("csrf".into())).await;
+        let req = test::TestRequest::post().uri("/?_method=PUT").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 403, "CSRF token is missing");
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_post_rejected_with_mismatched_csrf_token() {
+        let app = test::init_service(setup_csrf_test_app("csrf".into())).await;
+        let req = test::TestRequest::post()
+            .uri("/?_method=PUT&csrf=abc")
+            .cookie(Cookie::new("csrf", "xyz"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 403, "CSRF token does not match the cookie");
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_post_rerouted_with_csrf_token_in_query() {
+        let app = test::init_service(setup_csrf_test_app("csrf".into())).await;
+        let req = test::TestRequest::post()
+            .uri("/?_method=PUT&csrf=abc")
+            .cookie(Cookie::new("csrf", "abc"))
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"PUT ", "POST request rerouted to PUT");
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_post_rerouted_with_csrf_token_in_header() {
+        let app = test::init_service(setup_csrf_test_app("csrf".into())).await;
+        let req = test::TestRequest::post()
+            .uri("/?_method=PUT")
+            .insert_header(("X-CSRF-Token", "abc"))
+            .cookie(Cookie::new("csrf", "abc"))
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"PUT ", "POST request rerouted to PUT");
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_post_rerouted_with_csrf_token_in_form_body() {
+        let app = test::init_service(setup_csrf_test_app("csrf".into())).await;
+        let req = test::TestRequest::post()
+            .uri("/?_method=PUT")
+            .insert_header(("Content-Type", "application/x-www-form-urlencoded"))
+            .cookie(Cookie::new("csrf", "abc"))
+            .set_payload("name=item&csrf=abc")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(
+            &resp[..],
+            b"PUT name=item&csrf=abc",
+            "POST request rerouted to PUT, body is still readable"
+        );
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_form_content_type_is_case_insensitive() {
+        let app = test::init_service(setup_csrf_test_app("csrf".into())).await;
+        let req = test::TestRequest::post()
+            .uri("/?_method=PUT")
+            .insert_header((
+                "Content-Type",
+                "Application/X-WWW-Form-Urlencoded; charset=utf-8",
+            ))
+            .cookie(Cookie::new("csrf", "abc"))
+            .set_payload("csrf=abc")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"PUT csrf=abc", "POST request rerouted to PUT");
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_post_rejected_with_csrf_token_in_oversized_form_body() {
+        let app =
+            test::init_service(setup_csrf_test_app(CsrfToken::new("csrf").body_limit(10))).await;
+        let req = test::TestRequest::post()
+            .uri("/?_method=PUT")
+            .insert_header(("Content-Type", "application/x-www-form-urlencoded"))
+            .cookie(Cookie::new("csrf", "abc"))
+            .set_payload("name=item&csrf=abc")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 403, "body over the limit is not searched");
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_post_rerouted_with_csrf_validator() {
+        let app = test::init_service(setup_csrf_test_app(
+            CsrfToken::new("csrf").validator(|_, token| token == "secret"),
+        ))
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/?_method=PUT&csrf=secret")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"PUT ", "validator accepted the token");
+
+        let req = test::TestRequest::post()
+            .uri("/?_method=PUT&csrf=wrong")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 403, "validator rejected the token");
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_post_without_method_parameter_not_checked_for_csrf_token() {
+        let app = test::init_service(setup_csrf_test_app("csrf".into())).await;
+        let req = test::TestRequest::post().uri("/").to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"POST", "plain POST is left alone");
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_strict_mode_rejection_has_allow_header() {
+        let app = test::init_service(
+            App::new()
+                .wrap(QueryMethod::builder().strict_mode(true).build().unwrap())
+                .route("/", web::get().to(|| async { "GET" })),
+        )
+        .await;
+        let req = test::TestRequest::get().uri("/?_method=PUT").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400, "Request failed in strict mode");
+        assert_eq!(
+            resp.headers().get(header::ALLOW).unwrap(),
+            "POST",
+            "Allow header lists POST"
+        );
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_strict_mode_policy_per_method() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    QueryMethod::builder()
+                        .strict_mode_policy(StrictModePolicy::StripAndContinue)
+                        .strict_mode_policy_for(
+                            Method::GET,
+                            StrictModePolicy::Reject {
+                                status: StatusCode::METHOD_NOT_ALLOWED,
+                            },
+                        )
+                        .strict_mode_policy_for(Method::PATCH, StrictModePolicy::PassThrough)
+                        .build()
+                        .unwrap(),
+                )
+                .default_service(web::to(|req: HttpRequest| async move {
+                    format!("{} {}", req.method(), req.query_string())
+                })),
+        )
+        .await;
+        let req = test::TestRequest::get().uri("/?_method=PUT").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 405, "GET is rejected");
+        assert_eq!(resp.headers().get(header::ALLOW).unwrap(), "POST");
+
+        let req = test::TestRequest::put()
+            .uri("/?_method=DELETE&id=1")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"PUT id=1", "parameter is stripped from PUT");
+
+        let req = test::TestRequest::patch()
+            .uri("/?_method=DELETE&id=1")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(
+            &resp[..],
+            b"PATCH _method=DELETE&id=1",
+            "PATCH is passed through"
+        );
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_parameter_stripped_when_not_rerouted() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    QueryMethod::builder()
+                        .always_strip_parameter()
+                        .exclude_paths(["/excluded"])
+                        .build()
+                        .unwrap(),
+                )
+                .default_service(web::to(|req: HttpRequest| async move {
+                    format!("{} {}", req.method(), req.uri())
+                })),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/items?_method=PUT&id=1")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"GET /items?id=1", "parameter is stripped");
+
+        let req = test::TestRequest::get()
+            .uri("/items?_method=PUT")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"GET /items", "URI is normalized");
+
+        let req = test::TestRequest::get()
+            .uri("/items?q=a+b&r=%2F%7E&_method=PUT&flag&_%6Dethod=PUT")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(
+            &resp[..],
+            b"GET /items?q=a+b&r=%2F%7E&flag",
+            "other parameters are kept as they were sent"
+        );
+
+        let req = test::TestRequest::post()
+            .uri("/items?_method=PUT&id=1")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"PUT /items?id=1", "rerouted request");
+
+        let req = test::TestRequest::get()
+            .uri("/excluded?_method=PUT")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(
+            &resp[..],
+            b"GET /excluded?_method=PUT",
+            "excluded path is untouched"
+        );
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_parameter_not_stripped_by_default() {
+        let app = test::init_service(App::new().wrap(QueryMethod::new()).default_service(web::to(
+            |req: HttpRequest| async move { format!("{} {}", req.method(), req.uri()) },
+        )))
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/items?_method=PUT")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"GET /items?_method=PUT", "parameter is kept");
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_post_rerouted_with_normalized_method_value() {
+        let app = test::init_service(App::new().wrap(QueryMethod::new()).default_service(web::to(
+            |req: HttpRequest| async move { req.method().to_string() },
+        )))
+        .await;
+        for value in ["delete", "Delete", "%44ELETE", "%64elete"] {
+            let req = test::TestRequest::post()
+                .uri(&format!("/?_method={}", value))
+                .to_request();
+            let resp = test::call_and_read_body(&app, req).await;
+            assert_eq!(&resp[..], b"DELETE", "{} rerouted to DELETE", value);
+        }
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_post_rerouted_without_case_normalization() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    QueryMethod::builder()
+                        .normalize_method_case(false)
+                        .build()
+                        .unwrap(),
+                )
+                .default_service(web::to(|req: HttpRequest| async move {
+                    req.method().to_string()
+                })),
+        )
+        .await;
+        let req = test::TestRequest::post().uri("/?_method=list").to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"list", "custom method keeps its case");
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_post_rerouted_with_method_alias() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    QueryMethod::builder()
+                        .method_alias("remove", Method::DELETE)
+                        .method_alias("Update", Method::PUT)
+                        .build()
+                        .unwrap(),
+                )
+                .default_service(web::to(|req: HttpRequest| async move {
+                    req.method().to_string()
+                })),
+        )
+        .await;
+        for (value, method) in [
+            ("remove", "DELETE"),
+            ("REMOVE", "DELETE"),
+            ("update", "PUT"),
+        ] {
+            let req = test::TestRequest::post()
+                .uri(&format!("/?_method={}", value))
+                .to_request();
+            let resp = test::call_and_read_body(&app, req).await;
+            assert_eq!(
+                &resp[..],
+                method.as_bytes(),
+                "{} rerouted to {}",
+                value,
+                method
+            );
+        }
+    }
+
+    fn filter_by_header(req: &ServiceRequest, _: &Method) -> OverrideDecision {
+        match req.headers().get("X-Override").map(|v| v.as_bytes()) {
+            Some(b"allow") => OverrideDecision::Allow,
+            Some(b"skip") => OverrideDecision::Skip,
+            _ => OverrideDecision::Reject,
+        }
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_filter_decides_rerouting() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    QueryMethod::builder()
+                        .filter(filter_by_header)
+                        .build()
+                        .unwrap(),
+                )
+                .default_service(web::to(|req: HttpRequest| async move {
+                    format!("{} {}", req.method(), req.query_string())
+                })),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/?_method=PUT")
+            .insert_header(("X-Override", "allow"))
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"PUT ", "filter allowed rerouting");
+
+        let req = test::TestRequest::post()
+            .uri("/?_method=PUT")
+            .insert_header(("X-Override", "skip"))
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"POST _method=PUT", "filter skipped rerouting");
+
+        let req = test::TestRequest::post().uri("/?_method=PUT").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 403, "filter rejected rerouting");
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_async_filter_decides_rerouting() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    QueryMethod::builder()
+                        .filter(|_: &ServiceRequest, method: &Method| {
+                            let method = method.clone();
+                            async move {
+                                if method == Method::DELETE {
+                                    OverrideDecision::Reject
+                                } else {
+                                    OverrideDecision::Allow
+                                }
+                            }
+                        })
+                        .build()
+                        .unwrap(),
+                )
+                .default_service(web::to(|req: HttpRequest| async move {
+                    req.method().to_string()
+                })),
+        )
+        .await;
+        let req = test::TestRequest::post().uri("/?_method=PUT").to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"PUT", "filter allowed rerouting");
+
+        let req = test::TestRequest::post()
+            .uri("/?_method=DELETE")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 403, "filter rejected rerouting");
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_events_reported() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let app = test::init_service(
+            App::new()
+                .wrap({
+                    let events = events.clone();
+                    QueryMethod::builder()
+                        .strict_mode(true)
+                        .on_event(move |event| events.lock().unwrap().push(event))
+                        .build()
+                        .unwrap()
+                })
+                .default_service(web::to(|| async { "OK" })),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/items?_method=PUT")
+            .to_request();
+        test::call_service(&app, req).await;
+        let req = test::TestRequest::get()
+            .uri("/items?_method=PUT")
+            .to_request();
+        test::call_service(&app, req).await;
+        let req = test::TestRequest::post()
+            .uri("/items?_method=NO:METHOD")
+            .to_request();
+        test::call_service(&app, req).await;
+        let req = test::TestRequest::post().uri("/items").to_request();
+        test::call_service(&app, req).await;
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                QueryMethodEvent::Rewritten {
+                    from: Method::POST,
+                    to: Method::PUT,
+                    path: "/items".to_string(),
+                },
+                QueryMethodEvent::Rejected {
+                    reason: RejectionReason::StrictMode,
+                    path: "/items".to_string(),
+                },
+                QueryMethodEvent::Rejected {
+                    reason: RejectionReason::BadMethodValue,
+                    path: "/items".to_string(),
+                },
+            ],
+            "rerouted and rejected requests are reported"
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test_log::test(actix_web::test)]
+    async fn test_metrics_counted() {
+        let metrics = QueryMethodMetrics::new();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(metrics.clone()))
+                .wrap(
+                    QueryMethod::builder()
+                        .require_csrf_token("csrf")
+                        .metrics(metrics.clone())
+                        .build()
+                        .unwrap(),
+                )
+                .route(
+                    "/metrics",
+                    web::get().to(|metrics: web::Data<QueryMethodMetrics>| async move {
+                        metrics.render_prometheus()
+                    }),
+                )
+                .default_service(web::to(|| async { "OK" })),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/?_method=PUT&csrf=abc")
+            .cookie(Cookie::new("csrf", "abc"))
+            .to_request();
+        test::call_service(&app, req).await;
+        let req = test::TestRequest::post().uri("/?_method=PUT").to_request();
+        test::call_service(&app, req).await;
+
+        assert_eq!(metrics.rewritten(&Method::PUT), 1);
+        assert_eq!(metrics.rejected(RejectionReason::CsrfTokenMissing), 1);
+
+        let req = test::TestRequest::get().uri("/metrics").to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        let resp_text = String::from_utf8_lossy(&resp[..]);
+        assert!(
+            resp_text.contains("query_method_rewritten_total{method=\"PUT\"} 1"),
+            "metrics can be scraped from a handler"
+        );
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_same_origin_enforced() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    QueryMethod::builder()
+                        .enforce_same_origin()
+                        .build()
+                        .unwrap(),
+                )
+                .default_service(web::to(|req: HttpRequest| async move {
+                    req.method().to_string()
+                })),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/?_method=PUT")
+            .insert_header(("Host", "example.com"))
+            .insert_header(("Origin", "http://example.com"))
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"PUT", "same origin is rerouted");
+
+        let req = test::TestRequest::post()
+            .uri("/?_method=PUT")
+            .insert_header(("Host", "example.com"))
+            .insert_header(("Referer", "http://example.com/items/1"))
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"PUT", "same origin referer is rerouted");
+
+        let req = test::TestRequest::post()
+            .uri("/?_method=PUT")
+            .insert_header(("Host", "example.com"))
+            .insert_header(("Origin", "http://evil.example"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 403, "cross origin is rejected");
+
+        let req = test::TestRequest::post()
+            .uri("/?_method=PUT")
+            .insert_header(("Host", "example.com"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 403, "missing origin is rejected");
+
+        let req = test::TestRequest::post()
+            .uri("/")
+            .insert_header(("Origin", "http://evil.example"))
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"POST", "plain POST is left alone");
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_allowed_origins_and_missing_origin() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    QueryMethod::builder()
+                        .allowed_origins(["https://app.example.com"])
+                        .allow_missing_origin(true)
+                        .build()
+                        .unwrap(),
+                )
+                .default_service(web::to(|req: HttpRequest| async move {
+                    req.method().to_string()
+                })),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/?_method=PUT")
+            .insert_header(("Origin", "https://APP.example.com"))
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"PUT", "allowed origin is rerouted");
+
+        let req = test::TestRequest::post()
+            .uri("/?_method=PUT")
+            .insert_header(("Host", "localhost:8080"))
+            .insert_header(("Origin", "http://localhost:8080"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            resp.status(),
+            403,
+            "origin not in the allowlist is rejected"
+        );
+
+        let req = test::TestRequest::post().uri("/?_method=PUT").to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"PUT", "missing origin is allowed");
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_skipped_scope_is_not_rerouted() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    QueryMethod::builder()
+                        .always_strip_parameter()
+                        .build()
+                        .unwrap(),
+                )
+                .service(
+                    web::scope("/webhooks")
+                        .wrap(SkipQueryMethod)
+                        .default_service(web::to(|req: HttpRequest| async move {
+                            format!("{} {}", req.method(), req.uri())
+                        })),
+                )
+                .default_service(web::to(|req: HttpRequest| async move {
+                    format!("{} {}", req.method(), req.uri())
+                })),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/webhooks/payment?_method=PUT&id=1")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(
+            &resp[..],
+            b"POST /webhooks/payment?_method=PUT&id=1",
+            "skipped scope gets the original request"
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/webhooks/payment?_method=PUT")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(
+            &resp[..],
+            b"GET /webhooks/payment?_method=PUT",
+            "skipped scope gets the parameter back"
+        );
+
+        let req = test::TestRequest::post()
+            .uri("/items?_method=PUT&id=1")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"PUT /items?id=1", "other paths are rerouted");
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_undone_reroute_is_not_reported() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let app = test::init_service(
+            App::new()
+                .wrap({
+                    let events = events.clone();
+                    QueryMethod::builder()
+                        .on_event(move |event| events.lock().unwrap().push(event))
+                        .build()
+                        .unwrap()
+                })
+                .service(
+                    web::scope("/legacy")
+                        .wrap(SkipQueryMethod)
+                        .default_service(web::to(|req: HttpRequest| async move {
+                            req.method().to_string()
+                        })),
+                )
+                .default_service(web::to(|req: HttpRequest| async move {
+                    req.method().to_string()
+                })),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/legacy/items?_method=PUT")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"POST", "skipped scope is not rerouted");
+        assert_eq!(
+            events.lock().unwrap().len(),
+            0,
+            "undone reroute not reported"
+        );
+
+        let req = test::TestRequest::post()
+            .uri("/items?_method=PUT")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"PUT", "other paths are rerouted");
+        assert_eq!(
+            events.lock().unwrap().as_slice(),
+            [QueryMethodEvent::Rewritten {
+                from: Method::POST,
+                to: Method::PUT,
+                path: "/items".to_string(),
+            }],
+            "reroute reported"
+        );
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_skip_marker_is_respected() {
+        let app = test::init_service(
+            App::new()
+                .wrap(QueryMethod::new())
+                .wrap(SkipQueryMethod)
+                .default_service(web::to(|req: HttpRequest| async move {
+                    req.method().to_string()
+                })),
+        )
+        .await;
+        let req = test::TestRequest::post().uri("/?_method=PUT").to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"POST", "marker disabled the middleware");
+    }
+
+    async fn echo_request(req: HttpRequest, body: web::Bytes) -> String {
+        format!(
+            "{} {} {}",
+            req.method(),
+            req.uri(),
+            String::from_utf8_lossy(&body)
+        )
+    }
+
+    fn is_webhook(req: &ServiceRequest) -> bool {
+        req.match_pattern().as_deref() == Some("/webhooks/{provider}")
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_skip_when_keeps_webhooks_from_being_rejected() {
+        let app = test::init_service(
+            App::new()
+                .wrap(QueryMethod::default())
+                .wrap(SkipQueryMethod::when(is_webhook))
+                .route("/webhooks/{provider}", web::post().to(echo_request))
+                .default_service(web::to(echo_request)),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/webhooks/x?_method=refund%20order")
+            .set_payload("id=1")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200, "webhook is not rejected");
+        let body = test::read_body(resp).await;
+        assert_eq!(
+            &body[..],
+            b"POST /webhooks/x?_method=refund%20order id=1",
+            "webhook gets the original request"
+        );
+
+        let req = test::TestRequest::post()
+            .uri("/items?_method=refund%20order")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400, "other paths are still checked");
+
+        let req = test::TestRequest::post()
+            .uri("/items?_method=PUT")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"PUT /items ", "other paths are rerouted");
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_skip_when_runs_before_any_checks() {
+        let filtered = Arc::new(Mutex::new(0));
+        let app = test::init_service(
+            App::new()
+                .wrap({
+                    let filtered = filtered.clone();
+                    QueryMethod::builder()
+                        .strict_mode(true)
+                        .require_csrf_token("csrf")
+                        .enforce_same_origin()
+                        .filter(move |_: &ServiceRequest, _: &Method| {
+                            *filtered.lock().unwrap() += 1;
+                            OverrideDecision::Reject
+                        })
+                        .build()
+                        .unwrap()
+                })
+                .wrap(SkipQueryMethod::when(is_webhook))
+                .route("/webhooks/{provider}", web::to(echo_request))
+                .default_service(web::to(echo_request)),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/webhooks/x?_method=refund%20order")
+            .insert_header(("Origin", "https://payments.example.com"))
+            .set_payload("id=1")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(
+            &resp[..],
+            b"POST /webhooks/x?_method=refund%20order id=1",
+            "invalid method value is not checked"
+        );
+
+        let req = test::TestRequest::post()
+            .uri("/webhooks/x?_method=PUT")
+            .insert_header(("Origin", "https://payments.example.com"))
+            .set_payload("id=1")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(
+            &resp[..],
+            b"POST /webhooks/x?_method=PUT id=1",
+            "filter, CSRF, and same origin are not checked"
+        );
+
+        let req = test::TestRequest::put()
+            .uri("/webhooks/x?_method=DELETE")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(
+            &resp[..],
+            b"PUT /webhooks/x?_method=DELETE ",
+            "strict mode is not checked"
+        );
+        assert_eq!(*filtered.lock().unwrap(), 0, "filter did not run");
+
+        let req = test::TestRequest::post()
+            .uri("/items?_method=PUT")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 403, "other paths are still checked");
+        assert_eq!(*filtered.lock().unwrap(), 1, "filter ran for other paths");
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_query_method_applied_guard() {
+        let app = test::init_service(
+            App::new().wrap(QueryMethod::new()).service(
+                web::resource("/")
+                    .route(
+                        web::put()
+                            .guard(QueryMethodApplied)
+                            .to(|| async { "rerouted PUT" }),
+                    )
+                    .route(web::put().to(|| async { "PUT" })),
+            ),
+        )
+        .await;
+        let req = test::TestRequest::post().uri("/?_method=PUT").to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"rerouted PUT", "guard matched rerouted request");
+
+        let req = test::TestRequest::put().uri("/").to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"PUT", "guard did not match plain PUT");
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn test_allowed_original_methods() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    QueryMethod::builder()
+                        .strict_mode(true)
+                        .allowed_original_methods([Method::POST, Method::PATCH])
+                        .allowed_original_methods_for_paths(
+                            ["/admin/cache"],
+                            [Method::POST, Method::GET],
+                        )
+                        .build()
+                        .unwrap(),
+                )
+                .default_service(web::to(|req: HttpRequest| async move {
+                    req.method().to_string()
+                })),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/admin/cache?_method=PURGE")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"PURGE", "GET request rerouted on allowed path");
+
+        let req = test::TestRequest::patch()
+            .uri("/admin/c%61che?_method=PURGE")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            resp.status(),
+            StatusCode::BAD_REQUEST,
+            "encoded path gets the methods allowed for the path"
+        );
+
+        let req = test::TestRequest::patch()
+            .uri("/admin/cache?_method=PURGE")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            resp.headers().get(header::ALLOW).unwrap(),
+            "POST, GET",
+            "Allow header lists the methods allowed for the path"
+        );
+
+        let req = test::TestRequest::patch()
+            .uri("/items?_method=PURGE")
+            .to_request();
+        let resp = test::call_and_read_body(&app, req).await;
+        assert_eq!(&resp[..], b"PURGE", "PATCH request rerouted elsewhere");
+
+        let req = test::TestRequest::get()
+            .uri("/items?_method=PURGE")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(resp.headers().get(header::ALLOW).unwrap(), "POST, PATCH");
+    }
 }
@@ -0,0 +1,214 @@
+//! Counters for the requests the middleware reroutes or rejects, enabled with
+//! the `metrics` feature.
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use actix_web::http::Method;
+
+use crate::{QueryMethodEvent, RejectionReason};
+
+#[derive(Clone, Debug, Default)]
+/// Counts the requests the middleware reroutes, per method they are rerouted
+/// to, and the requests it rejects, per reason.
+///
+/// Any token is a valid custom method, so requests rerouted to custom methods
+/// like `LIST` are all counted together as `other`. Otherwise clients could
+/// make up new methods to grow the counters without limit.
+///
+/// This is a handle: clones share the same counters. Give one to the
+/// middleware with [`QueryMethodBuilder::metrics`](crate::QueryMethodBuilder::metrics),
+/// and register another as app data to read the counters from a handler, for
+/// example to serve them to Prometheus.
+///
+/// ```rs
+/// let metrics = QueryMethodMetrics::new();
+/// App::new()
+///     .app_data(web::Data::new(metrics.clone()))
+///     .wrap(QueryMethod::builder().metrics(metrics).build().unwrap())
+///     .route(
+///         "/metrics",
+///         web::get().to(|metrics: web::Data<QueryMethodMetrics>| async move {
+///             metrics.render_prometheus()
+///         }),
+///     )
+/// ```
+pub struct QueryMethodMetrics {
+    inner: Arc<Counters>,
+}
+
+/// The methods that get their own rewritten counter.
+const METHODS: [Method; 9] = [
+    Method::GET,
+    Method::POST,
+    Method::PUT,
+    Method::DELETE,
+    Method::HEAD,
+    Method::OPTIONS,
+    Method::CONNECT,
+    Method::PATCH,
+    Method::TRACE,
+];
+
+#[derive(Debug, Default)]
+struct Counters {
+    /// One counter for each of [`METHODS`], followed by one for all other
+    /// methods.
+    rewritten: [AtomicU64; METHODS.len() + 1],
+    rejected: [AtomicU64; RejectionReason::ALL.len()],
+}
+
+/// The position of the method's counter in [`Counters::rewritten`].
+fn method_index(method: &Method) -> usize {
+    METHODS
+        .iter()
+        .position(|m| m == method)
+        .unwrap_or(METHODS.len())
+}
+
+impl QueryMethodMetrics {
+    /// Create a new set of counters, all starting at zero.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many requests were rerouted to this method. For a custom method,
+    /// this is how many requests were rerouted to any custom method.
+    pub fn rewritten(&self, method: &Method) -> u64 {
+        self.inner.rewritten[method_index(method)].load(Ordering::Relaxed)
+    }
+
+    /// How many requests were rerouted, to any method.
+    pub fn rewritten_total(&self) -> u64 {
+        self.inner
+            .rewritten
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// How many requests were rejected for this reason.
+    pub fn rejected(&self, reason: RejectionReason) -> u64 {
+        self.inner.rejected[reason.index()].load(Ordering::Relaxed)
+    }
+
+    /// How many requests were rejected, for any reason.
+    pub fn rejected_total(&self) -> u64 {
+        self.inner
+            .rejected
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Render the counters in the Prometheus text format, as the
+    /// `query_method_rewritten_total` and `query_method_rejected_total`
+    /// counters. Custom methods are all labelled `method="other"`.
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::new();
+        out.push_str(
+            "# HELP query_method_rewritten_total Requests rerouted by the query method middleware.\n\
+             # TYPE query_method_rewritten_total counter\n",
+        );
+        let labels = METHODS.iter().map(Method::as_str).chain(["other"]);
+        for (label, count) in labels.zip(&self.inner.rewritten) {
+            // Writing to a string can't fail.
+            let _ = writeln!(
+                out,
+                "query_method_rewritten_total{{method=\"{}\"}} {}",
+                label,
+                count.load(Ordering::Relaxed)
+            );
+        }
+        out.push_str(
+            "# HELP query_method_rejected_total Requests rejected by the query method middleware.\n\
+             # TYPE query_method_rejected_total counter\n",
+        );
+        for reason in RejectionReason::ALL {
+            let _ = writeln!(
+                out,
+                "query_method_rejected_total{{reason=\"{}\"}} {}",
+                reason,
+                self.rejected(reason)
+            );
+        }
+        out
+    }
+
+    pub(crate) fn record(&self, event: &QueryMethodEvent) {
+        match event {
+            QueryMethodEvent::Rewritten { to, .. } => {
+                self.inner.rewritten[method_index(to)].fetch_add(1, Ordering::Relaxed);
+            }
+            QueryMethodEvent::Rejected { reason, .. } => {
+                self.inner.rejected[reason.index()].fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_are_counted() {
+        let metrics = QueryMethodMetrics::new();
+        let rewritten = |to: Method| QueryMethodEvent::Rewritten {
+            from: Method::POST,
+            to,
+            path: "/".to_string(),
+        };
+        metrics.record(&rewritten(Method::PUT));
+        metrics.record(&rewritten(Method::PUT));
+        metrics.record(&rewritten(Method::DELETE));
+        metrics.clone().record(&QueryMethodEvent::Rejected {
+            reason: RejectionReason::StrictMode,
+            path: "/".to_string(),
+        });
+
+        assert_eq!(metrics.rewritten(&Method::PUT), 2);
+        assert_eq!(metrics.rewritten(&Method::DELETE), 1);
+        assert_eq!(metrics.rewritten(&Method::PATCH), 0);
+        assert_eq!(metrics.rewritten_total(), 3);
+        assert_eq!(metrics.rejected(RejectionReason::StrictMode), 1);
+        assert_eq!(metrics.rejected(RejectionReason::BadMethodValue), 0);
+        assert_eq!(metrics.rejected_total(), 1);
+    }
+
+    #[test]
+    fn test_render_prometheus() {
+        let metrics = QueryMethodMetrics::new();
+        metrics.record(&QueryMethodEvent::Rewritten {
+            from: Method::POST,
+            to: Method::PUT,
+            path: "/".to_string(),
+        });
+        let rendered = metrics.render_prometheus();
+        assert!(rendered.contains("# TYPE query_method_rewritten_total counter\n"));
+        assert!(rendered.contains("query_method_rewritten_total{method=\"PUT\"} 1\n"));
+        assert!(rendered.contains("query_method_rejected_total{reason=\"strict_mode\"} 0\n"));
+    }
+
+    #[test]
+    fn test_custom_methods_share_a_counter() {
+        let metrics = QueryMethodMetrics::new();
+        for method in ["LIST", "JUNK1", "JUNK2"] {
+            metrics.record(&QueryMethodEvent::Rewritten {
+                from: Method::POST,
+                to: Method::from_bytes(method.as_bytes()).unwrap(),
+                path: "/".to_string(),
+            });
+        }
+        assert_eq!(metrics.rewritten(&Method::from_bytes(b"LIST").unwrap()), 3);
+        assert_eq!(metrics.rewritten(&Method::PUT), 0);
+        let rendered = metrics.render_prometheus();
+        assert!(rendered.contains("query_method_rewritten_total{method=\"other\"} 3\n"));
+        assert!(!rendered.contains("JUNK"));
+        assert_eq!(
+            rendered.lines().count(),
+            2 + 10 + 2 + RejectionReason::ALL.len()
+        );
+    }
+}
@@ -0,0 +1,237 @@
+//! Checking that rerouted requests come from an allowed origin, configured
+//! with [`QueryMethodBuilder::enforce_same_origin`](crate::QueryMethodBuilder::enforce_same_origin).
+use std::str::FromStr;
+
+use actix_web::dev::ServiceRequest;
+use actix_web::http::{header, Uri};
+
+use crate::RejectionReason;
+
+#[derive(Clone, Debug, Default)]
+/// The origins that rerouted requests may come from.
+pub(crate) struct SameOrigin {
+    /// Normalized origins like `https://example.com`. When empty, only the
+    /// origin of the server itself is allowed.
+    pub(crate) allowed_origins: Vec<String>,
+    /// Whether requests without an `Origin` or `Referer` header are allowed.
+    pub(crate) allow_missing: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The outcome of checking the origin of a request.
+pub(crate) enum OriginCheck {
+    Allowed,
+    Missing,
+    CrossOrigin,
+}
+
+impl OriginCheck {
+    /// Why to reject the request, and what to tell the client, if it has to
+    /// be rejected.
+    pub(crate) fn rejection(self) -> Option<(RejectionReason, &'static str)> {
+        match self {
+            Self::Allowed => None,
+            Self::Missing => Some((RejectionReason::OriginMissing, "Request origin is missing")),
+            Self::CrossOrigin => Some((
+                RejectionReason::CrossOrigin,
+                "Request origin is not allowed",
+            )),
+        }
+    }
+}
+
+impl SameOrigin {
+    pub(crate) fn check(&self, req: &ServiceRequest) -> OriginCheck {
+        let Some(origin) = request_origin(req) else {
+            return if self.allow_missing {
+                OriginCheck::Allowed
+            } else {
+                OriginCheck::Missing
+            };
+        };
+
+        let allowed = if self.allowed_origins.is_empty() {
+            server_origin(req).is_some_and(|server| server == origin)
+        } else {
+            self.allowed_origins.contains(&origin)
+        };
+        if allowed {
+            OriginCheck::Allowed
+        } else {
+            OriginCheck::CrossOrigin
+        }
+    }
+}
+
+/// The origin the request came from, using the `Origin` header if there is
+/// one and the `Referer` header otherwise. A `null` origin, which browsers
+/// send for privacy sensitive contexts, never matches any allowed origin, and
+/// neither does a header that can't be parsed. Only a request without either
+/// header has no origin.
+fn request_origin(req: &ServiceRequest) -> Option<String> {
+    let headers = req.headers();
+    let origin = if let Some(origin) = headers.get(header::ORIGIN) {
+        origin.to_str().ok().and_then(normalize_origin)
+    } else {
+        let referer = headers.get(header::REFERER)?;
+        referer
+            .to_str()
+            .ok()
+            .and_then(|referer| Uri::from_str(referer).ok())
+            .and_then(|uri| origin_of(&uri))
+    };
+    Some(origin.unwrap_or_else(|| "null".to_string()))
+}
+
+/// The origin of the server itself, from the `Host` header and whether the
+/// server is listening with TLS. This deliberately ignores `Forwarded` and
+/// `X-Forwarded-*` headers, which clients can send themselves.
+fn server_origin(req: &ServiceRequest) -> Option<String> {
+    let host = match req.uri().authority() {
+        Some(authority) => authority.as_str(),
+        None => req.headers().get(header::HOST)?.to_str().ok()?,
+    };
+    let scheme = req
+        .uri()
+        .scheme_str()
+        .unwrap_or(if req.app_config().secure() {
+            "https"
+        } else {
+            "http"
+        });
+    normalize_origin(&format!("{}://{}", scheme, host))
+}
+
+/// The origin of a URI, like `https://example.com:8443`, leaving out the
+/// port if it is the default port for the scheme.
+fn origin_of(uri: &Uri) -> Option<String> {
+    let scheme = uri.scheme_str()?.to_ascii_lowercase();
+    let host = uri.host()?.to_ascii_lowercase();
+    let default_port = match scheme.as_str() {
+        "http" => Some(80),
+        "https" => Some(443),
+        _ => None,
+    };
+    Some(match uri.port_u16() {
+        Some(port) if Some(port) != default_port => format!("{}://{}:{}", scheme, host, port),
+        _ => format!("{}://{}", scheme, host),
+    })
+}
+
+/// Normalize an origin like `https://Example.com:443` to `https://example.com`.
+/// Returns `None` if this is not an origin, for example because it has a path.
+pub(crate) fn normalize_origin(origin: &str) -> Option<String> {
+    let uri = Uri::from_str(origin).ok()?;
+    let has_path = uri
+        .path_and_query()
+        .is_some_and(|pq| !pq.as_str().is_empty() && pq.as_str() != "/");
+    if has_path || origin.ends_with('/') {
+        return None;
+    }
+    origin_of(&uri)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::HeaderValue;
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    #[test]
+    fn test_unparseable_origin_is_cross_origin() {
+        let same_origin = SameOrigin {
+            allowed_origins: Vec::new(),
+            allow_missing: true,
+        };
+        let non_ascii = HeaderValue::from_bytes(b"http://localhost:8080\xe9").unwrap();
+        for (name, value) in [
+            (header::ORIGIN, non_ascii.clone()),
+            (header::REFERER, non_ascii),
+            (header::REFERER, HeaderValue::from_static("/relative/path")),
+        ] {
+            let req = TestRequest::default()
+                .insert_header((name, value.clone()))
+                .to_srv_request();
+            assert_eq!(
+                same_origin.check(&req),
+                OriginCheck::CrossOrigin,
+                "{:?} is cross origin",
+                value
+            );
+        }
+        let req = TestRequest::default().to_srv_request();
+        assert_eq!(same_origin.check(&req), OriginCheck::Allowed);
+    }
+
+    #[test]
+    fn test_server_origin_ignores_default_ports() {
+        let same_origin = SameOrigin::default();
+        for (host, origin) in [
+            ("example.com:80", "http://example.com"),
+            ("example.com", "http://example.com:80"),
+            ("Example.com:80", "http://example.com:80"),
+        ] {
+            let req = TestRequest::default()
+                .insert_header((header::HOST, host))
+                .insert_header((header::ORIGIN, origin))
+                .to_srv_request();
+            assert_eq!(
+                same_origin.check(&req),
+                OriginCheck::Allowed,
+                "{} is the origin of {}",
+                origin,
+                host
+            );
+        }
+    }
+
+    #[test]
+    fn test_server_origin_ignores_forwarded_headers() {
+        let same_origin = SameOrigin::default();
+        for (name, value) in [
+            ("X-Forwarded-Host", "evil.example"),
+            ("Forwarded", "host=evil.example"),
+        ] {
+            let req = TestRequest::default()
+                .insert_header((header::HOST, "example.com"))
+                .insert_header((name, value))
+                .insert_header((header::ORIGIN, "http://evil.example"))
+                .to_srv_request();
+            assert_eq!(
+                same_origin.check(&req),
+                OriginCheck::CrossOrigin,
+                "{} is ignored",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_normalize_origin() {
+        assert_eq!(
+            normalize_origin("https://Example.com"),
+            Some("https://example.com".to_string())
+        );
+        assert_eq!(
+            normalize_origin("http://localhost:8080"),
+            Some("http://localhost:8080".to_string())
+        );
+        assert_eq!(
+            normalize_origin("http://example.com:80"),
+            Some("http://example.com".to_string())
+        );
+        assert_eq!(
+            normalize_origin("https://example.com:443"),
+            Some("https://example.com".to_string())
+        );
+        assert_eq!(
+            normalize_origin("http://example.com:443"),
+            Some("http://example.com:443".to_string())
+        );
+        assert_eq!(normalize_origin("https://example.com/path"), None);
+        assert_eq!(normalize_origin("https://example.com/"), None);
+        assert_eq!(normalize_origin("example.com"), None);
+        assert_eq!(normalize_origin("null"), None);
+    }
+}
@@ -0,0 +1,124 @@
+//! Matching request paths against the patterns given to
+//! [`QueryMethodBuilder::include_paths`](crate::QueryMethodBuilder::include_paths) and
+//! [`QueryMethodBuilder::exclude_paths`](crate::QueryMethodBuilder::exclude_paths).
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A pattern to match request paths against.
+///
+/// Patterns without any `*` are prefixes: `/admin` matches `/admin` and
+/// anything under it like `/admin/users`, but not `/administrator`. Patterns
+/// with a `*` in them are globs, where `*` matches anything within a single
+/// path segment and a `**` segment matches any number of segments.
+pub(crate) enum PathPattern {
+    Prefix(String),
+    Glob(Vec<String>),
+}
+
+impl PathPattern {
+    pub(crate) fn new(pattern: &str) -> Self {
+        if pattern.contains('*') {
+            Self::Glob(pattern.split('/').map(ToString::to_string).collect())
+        } else {
+            Self::Prefix(pattern.to_string())
+        }
+    }
+
+    /// Create the pattern, or return `None` if the pattern is not valid.
+    pub(crate) fn parse(pattern: &str) -> Option<Self> {
+        let valid = pattern.starts_with('/')
+            && !pattern.contains(['?', '#'])
+            && pattern
+                .split('/')
+                .all(|segment| segment == "**" || !segment.contains("**"));
+        valid.then(|| Self::new(pattern))
+    }
+
+    pub(crate) fn matches(&self, path: &str) -> bool {
+        match self {
+            Self::Prefix(prefix) => match path.strip_prefix(prefix.as_str()) {
+                Some(rest) => rest.is_empty() || prefix.ends_with('/') || rest.starts_with('/'),
+                None => false,
+            },
+            Self::Glob(segments) => {
+                let path: Vec<&str> = path.split('/').collect();
+                glob_segments_match(segments, &path)
+            }
+        }
+    }
+}
+
+/// Match path segments against pattern segments, where a `**` pattern segment
+/// can consume any number of path segments.
+fn glob_segments_match(pattern: &[String], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=path.len()).any(|skip| glob_segments_match(rest, &path[skip..]))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((segment, path_rest)) => {
+                glob_segment_match(first.as_bytes(), segment.as_bytes())
+                    && glob_segments_match(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+/// Match a single path segment against a pattern segment, where `*` matches
+/// any number of characters.
+fn glob_segment_match(pattern: &[u8], segment: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => segment.is_empty(),
+        Some((b'*', rest)) => {
+            (0..=segment.len()).any(|skip| glob_segment_match(rest, &segment[skip..]))
+        }
+        Some((c, rest)) => match segment.split_first() {
+            Some((s, segment_rest)) => c == s && glob_segment_match(rest, segment_rest),
+            None => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefix_matches_whole_segments() {
+        let pattern = PathPattern::new("/admin");
+        assert!(pattern.matches("/admin"));
+        assert!(pattern.matches("/admin/"));
+        assert!(pattern.matches("/admin/users/1"));
+        assert!(!pattern.matches("/administrator"));
+        assert!(!pattern.matches("/api/admin"));
+    }
+
+    #[test]
+    fn test_prefix_with_trailing_slash() {
+        let pattern = PathPattern::new("/");
+        assert!(pattern.matches("/"));
+        assert!(pattern.matches("/anything/at/all"));
+    }
+
+    #[test]
+    fn test_glob_double_star() {
+        let pattern = PathPattern::new("/admin/**");
+        assert!(pattern.matches("/admin"));
+        assert!(pattern.matches("/admin/users"));
+        assert!(pattern.matches("/admin/users/1/edit"));
+        assert!(!pattern.matches("/api/admin/users"));
+    }
+
+    #[test]
+    fn test_glob_single_star() {
+        let pattern = PathPattern::new("/items/*/edit");
+        assert!(pattern.matches("/items/1/edit"));
+        assert!(!pattern.matches("/items/1/2/edit"));
+        assert!(!pattern.matches("/items/edit"));
+
+        let pattern = PathPattern::new("/files/*.html");
+        assert!(pattern.matches("/files/index.html"));
+        assert!(!pattern.matches("/files/index.json"));
+    }
+}
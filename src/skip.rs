@@ -0,0 +1,190 @@
+//! Opting some scopes or routes out of the middleware.
+use std::cell::Cell;
+use std::fmt;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+use actix_web::dev::{Service, ServiceRequest, Transform};
+use actix_web::http::Uri;
+use actix_web::HttpMessage;
+
+use crate::OriginalMethod;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// Keeps requests away from the [`QueryMethod`](crate::QueryMethod)
+/// middleware.
+///
+/// `SkipQueryMethod` is a marker: a `QueryMethod` that finds it in the
+/// request extensions passes the request on untouched, without checking or
+/// rejecting it. How you get the marker there depends on what you need.
+///
+/// For endpoints that must never be touched by the middleware, like webhooks
+/// where third parties send a `_method` parameter as plain data, use
+/// [`SkipQueryMethod::when`] and wrap your app with it after `QueryMethod`, so
+/// that it sees requests first:
+///
+/// ```rs
+/// App::new()
+///     .wrap(QueryMethod::default())
+///     .wrap(SkipQueryMethod::when(|req: &ServiceRequest| {
+///         req.headers().contains_key("Stripe-Signature")
+///     }))
+///     .service(web::scope("/webhooks").route("/stripe", web::post().to(stripe_webhook)))
+/// ```
+///
+/// You can also wrap a scope or resource with `SkipQueryMethod` itself:
+///
+/// ```rs
+/// App::new()
+///     .wrap(QueryMethod::default())
+///     .service(
+///         web::scope("/legacy")
+///             .wrap(SkipQueryMethod)
+///             .route("/items", web::post().to(create_item)),
+///     )
+/// ```
+///
+/// Because the `QueryMethod` wrapping your whole app sees the request before
+/// it is routed to the scope, `SkipQueryMethod` can only undo what it did: a
+/// rerouted request gets its original method back, the method parameter is
+/// put back into the query string, and the rerouting is not reported as a
+/// [`QueryMethodEvent`](crate::QueryMethodEvent).
+///
+/// It can't undo a rejection though, since rejected requests never reach the
+/// scope, and the filter, CSRF, and same-origin checks have already run by
+/// then. Requests to the scope that have the method parameter are still
+/// rejected when:
+///
+/// - the method parameter is not a valid method, like `?_method=refund%20order`,
+/// - the request was sent with a method that can't be rerouted, and the
+///   [`StrictModePolicy`](crate::StrictModePolicy) for it is to reject,
+/// - the filter rejects the request,
+/// - a CSRF token is required, and the request doesn't have a valid one,
+/// - same origin is enforced, and the request came from another origin.
+pub struct SkipQueryMethod;
+
+impl SkipQueryMethod {
+    /// A middleware that marks the requests for which `predicate` returns
+    /// `true`, so that [`QueryMethod`](crate::QueryMethod) leaves them alone.
+    /// It has to see requests before `QueryMethod` does, so wrap it around
+    /// `QueryMethod`: in Actix Web, the middleware you wrap last runs first.
+    ///
+    /// The predicate runs before the request is routed, but you can still
+    /// check which route it will go to with `req.match_pattern()`, like
+    /// `req.match_pattern().as_deref() == Some("/webhooks/{provider}")`.
+    pub fn when<F>(predicate: F) -> SkipQueryMethodWhen
+    where
+        F: Fn(&ServiceRequest) -> bool + 'static,
+    {
+        SkipQueryMethodWhen {
+            predicate: Rc::new(predicate),
+        }
+    }
+}
+
+/// A function that decides if a request should skip the middleware.
+type SkipPredicate = dyn Fn(&ServiceRequest) -> bool;
+
+#[derive(Clone)]
+/// Marks the requests a function picks with [`SkipQueryMethod`]. Create it
+/// with [`SkipQueryMethod::when`].
+pub struct SkipQueryMethodWhen {
+    predicate: Rc<SkipPredicate>,
+}
+
+impl fmt::Debug for SkipQueryMethodWhen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SkipQueryMethodWhen")
+            .field("predicate", &"<function>")
+            .finish()
+    }
+}
+
+/// The URI of a request before the middleware changed it.
+#[derive(Clone, Debug)]
+pub(crate) struct OriginalUri(pub(crate) Uri);
+
+/// Set when [`SkipQueryMethod`] undoes a rerouting, so that the middleware
+/// doesn't report it.
+#[derive(Clone, Debug)]
+pub(crate) struct RerouteUndone(pub(crate) Rc<Cell<bool>>);
+
+impl<S> Transform<S, ServiceRequest> for SkipQueryMethod
+where
+    S: Service<ServiceRequest>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type InitError = ();
+    type Transform = SkipQueryMethodMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SkipQueryMethodMiddleware {
+            service,
+            predicate: None,
+        }))
+    }
+}
+
+impl<S> Transform<S, ServiceRequest> for SkipQueryMethodWhen
+where
+    S: Service<ServiceRequest>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type InitError = ();
+    type Transform = SkipQueryMethodMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SkipQueryMethodMiddleware {
+            service,
+            predicate: Some(self.predicate.clone()),
+        }))
+    }
+}
+
+pub struct SkipQueryMethodMiddleware<S> {
+    service: S,
+    /// Which requests to mark, or `None` to mark all of them.
+    predicate: Option<Rc<SkipPredicate>>,
+}
+
+impl<S> Service<ServiceRequest> for SkipQueryMethodMiddleware<S>
+where
+    S: Service<ServiceRequest>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    actix_service::forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        if let Some(predicate) = &self.predicate {
+            if !predicate(&req) {
+                return self.service.call(req);
+            }
+        }
+
+        let (original_method, original_uri) = {
+            let mut extensions = req.extensions_mut();
+            extensions.insert(SkipQueryMethod);
+            if let Some(RerouteUndone(undone)) = extensions.remove::<RerouteUndone>() {
+                undone.set(true);
+            }
+            (
+                extensions.remove::<OriginalMethod>(),
+                extensions.remove::<OriginalUri>(),
+            )
+        };
+        if let Some(OriginalMethod(method)) = original_method {
+            req.head_mut().method = method;
+        }
+        if let Some(OriginalUri(uri)) = original_uri {
+            req.head_mut().uri = uri;
+        }
+        self.service.call(req)
+    }
+}
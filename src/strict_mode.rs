@@ -0,0 +1,43 @@
+//! What to do with requests that have the method parameter, but can't be
+//! rerouted.
+use actix_web::http::StatusCode;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// What the middleware does with a request that has the method parameter but
+/// was sent with a method that can't be rerouted, like `GET /x?_method=PUT`.
+///
+/// You can pick a policy for all such requests with
+/// [`QueryMethodBuilder::strict_mode_policy`](crate::QueryMethodBuilder::strict_mode_policy),
+/// and override it for specific methods with
+/// [`QueryMethodBuilder::strict_mode_policy_for`](crate::QueryMethodBuilder::strict_mode_policy_for).
+pub enum StrictModePolicy {
+    /// Respond to the request with this status code, without passing it on to
+    /// your server. The response has an `Allow` header listing the methods
+    /// that can be rerouted. `405 Method Not Allowed` fits this best, while
+    /// `400 Bad Request` is what strict mode uses.
+    Reject {
+        /// The status code of the response. This must be an error code.
+        status: StatusCode,
+    },
+    /// Remove the method parameter from the query string, then pass the
+    /// request on to your server with its method unchanged.
+    StripAndContinue,
+    /// Pass the request on to your server unchanged. This is the default.
+    #[default]
+    PassThrough,
+}
+
+impl StrictModePolicy {
+    /// The policy used when strict mode is enabled.
+    pub(crate) const STRICT: Self = Self::Reject {
+        status: StatusCode::BAD_REQUEST,
+    };
+
+    /// Whether this policy can be used.
+    pub(crate) fn is_valid(&self) -> bool {
+        match self {
+            Self::Reject { status } => status.is_client_error() || status.is_server_error(),
+            Self::StripAndContinue | Self::PassThrough => true,
+        }
+    }
+}